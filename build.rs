@@ -1,3 +1,4 @@
+use glob::glob;
 use regex::Regex;
 use std::io;
 use std::io::prelude::*;
@@ -78,6 +79,125 @@ fn main() {
         println!("cargo:rustc-link-lib=winmm");
     }
 
+    generate_bindings();
+    generate_engine_functions().unwrap();
+}
+
+/// Generates the bindings for the engine's C interface.
+fn generate_bindings() {
+    println!("cargo:rerun-if-changed=openclonk/include/c4script/c4script.h");
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("c4script_sys.rs");
+    bindgen::Builder::default()
+        .header("openclonk/include/c4script/c4script.h")
+        .allowlist_type("c4s_.*")
+        .allowlist_function("c4s_.*")
+        .generate()
+        .expect("Unable to generate bindings for c4script.h")
+        .write_to_file(out)
+        .unwrap();
+}
+
+/// Collects all engine functions with their documentation from the script
+/// reference. The generated table is sorted by name and uses the types from
+/// `src/engine.rs`.
+fn generate_engine_functions() -> io::Result<()> {
+    let mut functions: Vec<(String, String)> = Vec::new();
+    for path in glob("openclonk/docs/sdk/script/fn/*.xml").unwrap().filter_map(Result::ok) {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let xml = read_file(path.to_str().unwrap())?;
+        let name = match path.file_stem() {
+            Some(stem) => stem.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        let func = xml_tag(&xml, "func").unwrap_or("");
+        let syntax = xml_tag(func, "syntax").unwrap_or("");
+        // Parameters have descriptions as well, so look at the rest only.
+        let rest = func.replacen(syntax, "", 1);
+
+        let params: Vec<String> = xml_tags(xml_tag(syntax, "params").unwrap_or(""), "param").iter()
+            .map(|param| format!(
+                "Param {{ ty: {:?}, name: {:?}, description: {:?}, optional: {} }}",
+                xml_text(xml_tag(param, "type").unwrap_or("")),
+                xml_text(xml_tag(param, "name").unwrap_or("")),
+                xml_text(xml_tag(param, "desc").unwrap_or("")),
+                param.contains("<optional"),
+            ))
+            .collect();
+        let examples: Vec<String> = xml_tags(xml_tag(&rest, "examples").unwrap_or(""), "example").iter()
+            .map(|example| format!(
+                "Example {{ code: {:?}, text: {:?} }}",
+                xml_code(xml_tag(example, "code").unwrap_or("")),
+                xml_text(xml_tag(example, "text").unwrap_or("")),
+            ))
+            .collect();
+        let description = [xml_tag(&rest, "desc"), xml_tag(&rest, "remark")].iter()
+            .filter_map(|text| text.map(xml_text))
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        functions.push((name.clone(), format!(
+            "    Function {{ name: {:?}, return_type: {:?}, params: &[{}], description: {:?}, examples: &[{}] }},\n",
+            name,
+            xml_text(xml_tag(syntax, "rtype").unwrap_or("")),
+            params.join(", "),
+            description,
+            examples.join(", "),
+        )));
+    }
+    functions.sort();
+    let mut code = String::from("pub static ENGINE_FUNCTIONS: &[Function] = &[\n");
+    code.extend(functions.into_iter().map(|(_, function)| function));
+    code.push_str("];\n");
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("engine_functions.rs");
+    write_file(out.to_str().unwrap(), &code)
+}
+
+/// Returns the contents of the first element with the given name.
+fn xml_tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    xml_tags(xml, name).into_iter().next()
+}
+
+/// Returns the contents of all elements with the given name. Nested elements
+/// of the same name are not supported.
+fn xml_tags<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let re = Regex::new(&format!(r"(?s)<{0}(?:\s[^>]*)?>(.*?)</{0}>", regex::escape(name))).unwrap();
+    re.captures_iter(xml).map(|c| c.get(1).unwrap().as_str()).collect()
+}
+
+/// Converts documentation markup to Markdown.
+fn xml_text(xml: &str) -> String {
+    let text = Regex::new(r"\s+").unwrap().replace_all(xml.trim(), " ");
+    let text = Regex::new(r"<br\s*/>\s*").unwrap().replace_all(&text, "  \n");
+    let text = Regex::new(r"(?s)<(?:funclink|code)>(.*?)</(?:funclink|code)>").unwrap().replace_all(&text, "`$1`");
+    let text = Regex::new(r"(?s)<(?:em|strong)>(.*?)</(?:em|strong)>").unwrap().replace_all(&text, "*$1*");
+    let text = Regex::new(r"<[^>]*>").unwrap().replace_all(&text, "");
+    xml_unescape(&text)
+}
+
+/// Extracts a code example, removing the indentation of the XML file.
+fn xml_code(xml: &str) -> String {
+    let code = xml_unescape(xml.trim_start_matches(&['\r', '\n'][..]).trim_end());
+    let indentation = |line: &str| line.len() - line.trim_start().len();
+    // The first line often starts right after the opening tag.
+    let indent = code.lines()
+        .skip(if code.lines().count() > 1 { 1 } else { 0 })
+        .filter(|line| !line.trim().is_empty())
+        .map(indentation)
+        .min()
+        .unwrap_or(0);
+    code.lines()
+        .map(|line| &line[indentation(line).min(indent)..])
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
 }
 
 fn read_file(path: &str) -> io::Result<String> {
@@ -0,0 +1,184 @@
+//! Quick fixes for diagnostics, for `textDocument/codeAction`.
+
+use crate::ast::{DirectiveKind, Script};
+use crate::diagnostics;
+use crate::engine;
+use crate::lexer;
+use crate::scope::{self, RefKind};
+use crate::utils::LineIndex;
+use lsp_types::*;
+use std::collections::HashMap;
+
+/// Builds the quick fixes for a diagnostic in a script. `includes` lists the
+/// definitions declaring a function, for offering to include them.
+pub fn quick_fixes(uri: &Url, script: &Script, code: &str, diagnostic: &Diagnostic, includes: &mut dyn FnMut(&str) -> Vec<String>) -> Vec<CodeAction> {
+    let index = LineIndex::new(code);
+    let offset = match index.offset(diagnostic.range.start) {
+        Some(offset) => offset,
+        None => return Vec::new(),
+    };
+    let fix = |title: String, edits: Vec<TextEdit>| {
+        let mut changes = HashMap::new();
+        changes.insert(uri.clone(), edits);
+        CodeAction {
+            title,
+            kind: Some(code_action_kind::QUICKFIX.to_string()),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            edit: Some(WorkspaceEdit { changes: Some(changes), ..WorkspaceEdit::default() }),
+            command: None,
+            // lsp-types misspells this field as `is_preferred`.
+            is_preferred: None,
+        }
+    };
+    let insert = |offset: usize, text: String| {
+        let pos = index.position(offset);
+        TextEdit { range: Range { start: pos, end: pos }, new_text: text }
+    };
+    // Diagnostics from clients that drop codes are classified by their text.
+    let id = match &diagnostic.code {
+        Some(NumberOrString::String(c)) => Some(c.as_str()),
+        Some(NumberOrString::Number(_)) => None,
+        None => diagnostics::message_code(&diagnostic.message),
+    };
+    let occurrence = scope::occurrence_at(script, offset);
+    let mut actions = Vec::new();
+    match id {
+        Some(diagnostics::UNKNOWN_IDENTIFIER) => {
+            let (ident, f) = match (occurrence, scope::enclosing_function(script, offset)) {
+                (Some(o), Some(f)) if o.kind == RefKind::Variable => (o.ident, f),
+                _ => return actions,
+            };
+            let title = format!("Declare \"{}\" as var", ident.name);
+            let rest = code[ident.span.end..].trim_start();
+            if rest.starts_with('=') && !rest.starts_with("==") {
+                // Turn the assignment into the declaration.
+                actions.push(fix(title, vec![insert(ident.span.start, "var ".to_string())]));
+            } else {
+                let indent = f.body.stmts.first()
+                    .map(|stmt| line_indent(code, stmt.span.start))
+                    .unwrap_or("\t");
+                let edit = insert(f.body.span.start + 1, format!("\n{}var {};", indent, ident.name));
+                actions.push(fix(title, vec![edit]));
+            }
+        },
+        Some(diagnostics::MISSING_SEMICOLON) => {
+            // After the statement, not after a comment following it.
+            let end = lexer::tokenize(code).into_iter()
+                .take_while(|token| token.span.end <= offset)
+                .filter(|token| !token.is_comment())
+                .last()
+                .map_or(0, |token| token.span.end);
+            actions.push(fix("Insert \";\"".to_string(), vec![insert(end, ";".to_string())]));
+        },
+        Some(diagnostics::UNKNOWN_FUNCTION) => {
+            let ident = match occurrence {
+                Some(o) if o.kind == RefKind::Call || o.kind == RefKind::MethodCall => o.ident,
+                _ => return actions,
+            };
+            if let Some(successor) = engine::successor(&ident.name) {
+                let edit = TextEdit { range: index.range(ident.span), new_text: successor.to_string() };
+                actions.push(fix(format!("Replace with \"{}\"", successor), vec![edit]));
+            }
+            for id in includes(&ident.name) {
+                let edit = insert_directive(script, code, &format!("#include {}", id));
+                actions.push(fix(format!("Add \"#include {}\"", id), vec![edit]));
+            }
+        },
+        Some(diagnostics::UNUSED_PARAMETER) => {
+            let ident = match occurrence {
+                Some(o) => o.ident,
+                None => return actions,
+            };
+            let title = format!("Mark \"{}\" as unused", ident.name);
+            actions.push(fix(title, vec![insert(ident.span.start, "_".to_string())]));
+        },
+        // Other warnings come from the engine, named as `#warning` expects.
+        Some(c) if diagnostic.severity == Some(DiagnosticSeverity::Warning) => {
+            let edit = insert_directive(script, code, &format!("#warning disable {}", c));
+            actions.push(fix(format!("Disable \"{}\" warnings in this script", c), vec![edit]));
+        },
+        _ => (),
+    }
+    actions
+}
+
+/// Adds a directive below the existing `#include`s and `#appendto`s, or after
+/// the header comment.
+fn insert_directive(script: &Script, code: &str, directive: &str) -> TextEdit {
+    let code_start = code.len() - code.trim_start().len();
+    let header = script.comments.first().filter(|c| c.start == code_start);
+    let (offset, before, after) = script.directives.iter()
+        .filter(|d| d.kind == DirectiveKind::Include || d.kind == DirectiveKind::Appendto)
+        .map(|d| d.span.end)
+        .max()
+        .map(|end| (end, "\n", ""))
+        .or_else(|| header.map(|c| (c.end, "\n\n", "")))
+        .unwrap_or((0, "", "\n\n"));
+    let pos = LineIndex::new(code).position(offset);
+    TextEdit { range: Range { start: pos, end: pos }, new_text: format!("{}{}{}", before, directive, after) }
+}
+
+/// Leading whitespace of the line containing the offset.
+fn line_indent(code: &str, offset: usize) -> &str {
+    let start = code[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = &code[start..];
+    &line[..line.len() - line.trim_start_matches(&[' ', '\t'][..]).len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn fixes(code: &str, diagnostic: Diagnostic) -> Vec<(String, Vec<TextEdit>)> {
+        let uri = Url::parse("file:///test/Script.c").unwrap();
+        let script = parser::parse(code);
+        quick_fixes(&uri, &script, code, &diagnostic, &mut |_| Vec::new()).into_iter()
+            .map(|action| {
+                let mut changes = action.edit.unwrap().changes.unwrap();
+                (action.title, changes.remove(&uri).unwrap())
+            })
+            .collect()
+    }
+
+    fn diagnostic(line: u64, character: u64, code: Option<&str>, message: &str) -> Diagnostic {
+        let pos = Position::new(line, character);
+        Diagnostic {
+            range: Range::new(pos, pos),
+            code: code.map(|c| NumberOrString::String(c.to_string())),
+            message: message.to_string(),
+            ..Diagnostic::default()
+        }
+    }
+
+    #[test]
+    fn inserts_semicolon_before_comment() {
+        let code = "func f()\n{\n\tFoo() // hi\n\tBar();\n}\n";
+        let fixes = fixes(code, diagnostic(3, 1, Some(diagnostics::MISSING_SEMICOLON), "';' expected"));
+        assert_eq!(fixes.len(), 1);
+        let edit = &fixes[0].1[0];
+        assert_eq!(edit.range.start, Position::new(2, 6));
+        assert_eq!(edit.new_text, ";");
+    }
+
+    #[test]
+    fn classifies_diagnostics_without_code_by_message() {
+        let code = "func f()\n{\n\tx = 1;\n}\n";
+        let fixes = fixes(code, diagnostic(2, 1, None, "unknown identifier: x"));
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(fixes[0].0, "Declare \"x\" as var");
+        assert_eq!(fixes[0].1[0].new_text, "var ");
+    }
+
+    #[test]
+    fn disables_engine_warnings_by_id() {
+        let code = "#include Foo\n\nfunc f(a)\n{\n\tvar a;\n}\n";
+        let mut warning = diagnostic(4, 5, Some("variable_shadows_variable"), "declaration of \"a\" shadows a parameter");
+        warning.severity = Some(DiagnosticSeverity::Warning);
+        let fixes = fixes(code, warning);
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(fixes[0].0, "Disable \"variable_shadows_variable\" warnings in this script");
+        assert_eq!(fixes[0].1[0].range.start, Position::new(0, 12));
+        assert_eq!(fixes[0].1[0].new_text, "\n#warning disable variable_shadows_variable");
+    }
+}
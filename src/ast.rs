@@ -0,0 +1,308 @@
+//! Syntax tree for C4Script as produced by `parser`.
+
+use crate::lexer::Span;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Default)]
+pub struct Script {
+    pub directives: Vec<Directive>,
+    pub items: Vec<Item>,
+    /// Spans of all comments, in source order.
+    pub comments: Vec<Span>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DirectiveKind {
+    Include,
+    Appendto,
+    Other(String),
+}
+
+#[derive(Debug)]
+pub struct Directive {
+    pub kind: DirectiveKind,
+    /// The first word after the directive, e.g. the definition ID for
+    /// `#include`.
+    pub argument: Option<Ident>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum Item {
+    Function(Function),
+    Vars(VarDecl),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Public,
+    Protected,
+    Private,
+    Global,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub visibility: Option<Visibility>,
+    pub name: Ident,
+    pub params: Vec<Param>,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Param {
+    pub ty: Option<Ident>,
+    pub name: Ident,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VarScope {
+    /// Function-local `var`.
+    Var,
+    /// Object-local `local`.
+    Local,
+    Static,
+    StaticConst,
+}
+
+#[derive(Debug)]
+pub struct VarDecl {
+    pub scope: VarScope,
+    pub vars: Vec<VarInit>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct VarInit {
+    pub name: Ident,
+    pub value: Option<Expr>,
+}
+
+#[derive(Debug)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum StmtKind {
+    Block(Block),
+    Vars(VarDecl),
+    Expr(Expr),
+    If { cond: Expr, then: Box<Stmt>, otherwise: Option<Box<Stmt>> },
+    While { cond: Expr, body: Box<Stmt> },
+    DoWhile { body: Box<Stmt>, cond: Expr },
+    For { init: Option<Box<Stmt>>, cond: Option<Expr>, step: Option<Expr>, body: Box<Stmt> },
+    /// `for (var x in list)`; `decl` is false if `var` was omitted.
+    ForIn { var: Ident, decl: bool, iter: Expr, body: Box<Stmt> },
+    Return(Option<Expr>),
+    Break,
+    Continue,
+    Empty,
+}
+
+#[derive(Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
+    Ident(Ident),
+    Number,
+    String,
+    /// `true`, `false`, `nil`, `this`
+    Constant,
+    /// Plain function call, including `inherited` and `_inherited`.
+    Call { name: Ident, args: Vec<Expr> },
+    /// `target->Name()` or `target->~Name()`
+    MethodCall { target: Box<Expr>, fail_safe: bool, name: Ident, args: Vec<Expr> },
+    /// `target.Name`
+    Member { target: Box<Expr>, name: Ident },
+    /// `target[index]`
+    Index { target: Box<Expr>, index: Box<Expr> },
+    Unary { operand: Box<Expr> },
+    Binary { lhs: Box<Expr>, rhs: Box<Expr> },
+    Array(Vec<Expr>),
+    /// `{ Key = value }`, optionally with `new Prototype` in front.
+    Proplist { prototype: Option<Ident>, props: Vec<Property> },
+    /// `func(...) { ... }` used as a value.
+    Function(Box<Function>),
+    Paren(Box<Expr>),
+    Error,
+}
+
+#[derive(Debug)]
+pub struct Property {
+    pub key: Ident,
+    pub value: Expr,
+    pub span: Span,
+}
+
+/// Reference to any node in the tree, used for generic traversal.
+#[derive(Clone, Copy, Debug)]
+pub enum Node<'a> {
+    Directive(&'a Directive),
+    Function(&'a Function),
+    Param(&'a Param),
+    VarDecl(&'a VarDecl),
+    Block(&'a Block),
+    Stmt(&'a Stmt),
+    Expr(&'a Expr),
+    Property(&'a Property),
+}
+
+impl Script {
+    /// Top-level nodes in source order.
+    pub fn nodes(&self) -> Vec<Node> {
+        let mut nodes: Vec<Node> = self.directives.iter().map(Node::Directive)
+            .chain(self.items.iter().map(|item| match item {
+                Item::Function(f) => Node::Function(f),
+                Item::Vars(v) => Node::VarDecl(v),
+            }))
+            .collect();
+        nodes.sort_by_key(|n| n.span().start);
+        nodes
+    }
+
+    /// Definition IDs named by `#include` and `#appendto`.
+    pub fn includes(&self) -> impl Iterator<Item = &Ident> {
+        self.directives.iter()
+            .filter(|d| d.kind == DirectiveKind::Include || d.kind == DirectiveKind::Appendto)
+            .filter_map(|d| d.argument.as_ref())
+    }
+
+    /// Arguments of all directives of the given kind, e.g. the IDs of only
+    /// the `#include`d definitions.
+    pub fn directive_arguments<'a>(&'a self, kind: &'a DirectiveKind) -> impl Iterator<Item = &'a Ident> {
+        self.directives.iter()
+            .filter(move |d| &d.kind == kind)
+            .filter_map(|d| d.argument.as_ref())
+    }
+
+    /// Returns all nodes containing the offset, outermost first.
+    pub fn nodes_at(&self, offset: usize) -> Vec<Node> {
+        let mut path = Vec::new();
+        let mut candidates = self.nodes();
+        while let Some(node) = candidates.into_iter().find(|n| n.span().contains(offset)) {
+            path.push(node);
+            candidates = node.children();
+        }
+        path
+    }
+
+    /// Calls `f` for every node in the tree, parents before children.
+    pub fn walk<'a>(&'a self, f: &mut dyn FnMut(Node<'a>)) {
+        for node in self.nodes() {
+            node.walk(f);
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Directive(d) => d.span,
+            Node::Function(f) => f.span,
+            Node::Param(p) => match &p.ty {
+                Some(ty) => ty.span.to(p.name.span),
+                None => p.name.span,
+            },
+            Node::VarDecl(v) => v.span,
+            Node::Block(b) => b.span,
+            Node::Stmt(s) => s.span,
+            Node::Expr(e) => e.span,
+            Node::Property(p) => p.span,
+        }
+    }
+
+    /// Direct children in source order.
+    pub fn children(&self) -> Vec<Node<'a>> {
+        let mut c = Vec::new();
+        match *self {
+            Node::Directive(_) | Node::Param(_) => (),
+            Node::Function(f) => {
+                c.extend(f.params.iter().map(Node::Param));
+                c.push(Node::Block(&f.body));
+            },
+            Node::VarDecl(v) => c.extend(v.vars.iter().filter_map(|v| v.value.as_ref()).map(Node::Expr)),
+            Node::Block(b) => c.extend(b.stmts.iter().map(Node::Stmt)),
+            Node::Property(p) => c.push(Node::Expr(&p.value)),
+            Node::Stmt(s) => match &s.kind {
+                StmtKind::Block(b) => c.push(Node::Block(b)),
+                StmtKind::Vars(v) => c.push(Node::VarDecl(v)),
+                StmtKind::Expr(e) => c.push(Node::Expr(e)),
+                StmtKind::If { cond, then, otherwise } => {
+                    c.push(Node::Expr(cond));
+                    c.push(Node::Stmt(then));
+                    c.extend(otherwise.iter().map(|s| Node::Stmt(s)));
+                },
+                StmtKind::While { cond, body } => {
+                    c.push(Node::Expr(cond));
+                    c.push(Node::Stmt(body));
+                },
+                StmtKind::DoWhile { body, cond } => {
+                    c.push(Node::Stmt(body));
+                    c.push(Node::Expr(cond));
+                },
+                StmtKind::For { init, cond, step, body } => {
+                    c.extend(init.iter().map(|s| Node::Stmt(s)));
+                    c.extend(cond.iter().map(Node::Expr));
+                    c.extend(step.iter().map(Node::Expr));
+                    c.push(Node::Stmt(body));
+                },
+                StmtKind::ForIn { iter, body, .. } => {
+                    c.push(Node::Expr(iter));
+                    c.push(Node::Stmt(body));
+                },
+                StmtKind::Return(e) => c.extend(e.iter().map(Node::Expr)),
+                StmtKind::Break | StmtKind::Continue | StmtKind::Empty => (),
+            },
+            Node::Expr(e) => match &e.kind {
+                ExprKind::Ident(_) | ExprKind::Number | ExprKind::String
+                    | ExprKind::Constant | ExprKind::Error => (),
+                ExprKind::Call { args, .. } => c.extend(args.iter().map(Node::Expr)),
+                ExprKind::MethodCall { target, args, .. } => {
+                    c.push(Node::Expr(target));
+                    c.extend(args.iter().map(Node::Expr));
+                },
+                ExprKind::Member { target, .. } => c.push(Node::Expr(target)),
+                ExprKind::Index { target, index } => {
+                    c.push(Node::Expr(target));
+                    c.push(Node::Expr(index));
+                },
+                ExprKind::Unary { operand } => c.push(Node::Expr(operand)),
+                ExprKind::Binary { lhs, rhs } => {
+                    c.push(Node::Expr(lhs));
+                    c.push(Node::Expr(rhs));
+                },
+                ExprKind::Array(items) => c.extend(items.iter().map(Node::Expr)),
+                ExprKind::Proplist { props, .. } => c.extend(props.iter().map(Node::Property)),
+                ExprKind::Function(f) => c.push(Node::Function(f)),
+                ExprKind::Paren(inner) => c.push(Node::Expr(inner)),
+            },
+        }
+        c
+    }
+
+    /// Calls `f` for this node and all descendants, parents before children.
+    pub fn walk(self, f: &mut dyn FnMut(Node<'a>)) {
+        f(self);
+        for child in self.children() {
+            child.walk(f);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Reader for packed c4group files.
+//!
+//! A packed group is a gzip stream with a modified magic. Inside is a
+//! scrambled header, a table of entry cores and the contents of all entries.
+//! Child groups are stored the same way (without compression) as entries of
+//! their parent.
+
+use flate2::read::GzDecoder;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path};
+
+const GROUP_ID: &[u8] = b"RedWolf Design GrpFolder";
+const HEADER_SIZE: usize = 204;
+const ENTRY_CORE_SIZE: usize = 316;
+/// Only the contents of these files are kept in memory.
+const TEXT_EXTENSIONS: &[&str] = &["c", "txt"];
+
+pub enum Entry {
+    /// A file, with its contents if it is a text file.
+    File(Option<Vec<u8>>),
+    Group(Group),
+}
+
+pub struct Group {
+    pub entries: BTreeMap<String, Entry>,
+}
+
+impl Group {
+    /// Reads a packed group file.
+    pub fn open(path: &Path) -> io::Result<Group> {
+        let mut data = fs::read(path)?;
+        if data.starts_with(&[0x1e, 0x8c]) {
+            data[0] = 0x1f;
+            data[1] = 0x8b;
+        }
+        Group::parse(&gunzip(&data)?)
+    }
+
+    fn parse(data: &[u8]) -> io::Result<Group> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if data.len() < HEADER_SIZE {
+            return Err(invalid("group header is truncated"));
+        }
+        let mut header = data[..HEADER_SIZE].to_vec();
+        unscramble(&mut header);
+        if !header.starts_with(GROUP_ID) {
+            return Err(invalid("not a c4group"));
+        }
+        let count = read_i32(&header, 36).max(0) as usize;
+        let contents_start = HEADER_SIZE + count * ENTRY_CORE_SIZE;
+        if data.len() < contents_start {
+            return Err(invalid("group entry table is truncated"));
+        }
+
+        let mut entries = BTreeMap::new();
+        for i in 0..count {
+            let core = &data[HEADER_SIZE + i * ENTRY_CORE_SIZE..][..ENTRY_CORE_SIZE];
+            let name_len = core[..260].iter().position(|&b| b == 0).unwrap_or(260);
+            let name = String::from_utf8_lossy(&core[..name_len]).into_owned();
+            let child_group = read_i32(core, 264) != 0;
+            let size = read_i32(core, 268).max(0) as usize;
+            let offset = contents_start + read_i32(core, 276).max(0) as usize;
+            let contents = data.get(offset..offset + size).ok_or_else(|| invalid("group entry is truncated"))?;
+            let entry = if child_group {
+                let contents = if contents.starts_with(&[0x1e, 0x8c]) {
+                    let mut packed = contents.to_vec();
+                    packed[0] = 0x1f;
+                    packed[1] = 0x8b;
+                    gunzip(&packed)?
+                } else {
+                    contents.to_vec()
+                };
+                Entry::Group(Group::parse(&contents)?)
+            } else {
+                let text = Path::new(&name).extension()
+                    .map_or(false, |ext| TEXT_EXTENSIONS.iter().any(|t| ext.eq_ignore_ascii_case(t)));
+                Entry::File(if text { Some(contents.to_vec()) } else { None })
+            };
+            entries.insert(name, entry);
+        }
+        Ok(Group { entries })
+    }
+
+    /// Looks up an entry by its path relative to the group.
+    pub fn get(&self, path: &Path) -> Option<&Entry> {
+        let mut group = self;
+        let mut components = path.components().peekable();
+        while let Some(component) = components.next() {
+            let name = match component {
+                Component::Normal(name) => name.to_str()?,
+                _ => return None,
+            };
+            // Names in groups are case-insensitive.
+            let entry = group.entries.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, e)| e)?;
+            if components.peek().is_none() {
+                return Some(entry);
+            }
+            group = match entry {
+                Entry::Group(g) => g,
+                Entry::File(_) => return None,
+            };
+        }
+        None
+    }
+}
+
+/// Reverses the scrambling of group headers: a XOR with 237 and swapping
+/// the first and last byte of every three.
+fn unscramble(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        *b ^= 237;
+    }
+    let mut i = 0;
+    while i + 2 < buf.len() {
+        buf.swap(i, i + 2);
+        i += 3;
+    }
+}
+
+fn read_i32(buf: &[u8], offset: usize) -> i32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    i32::from_le_bytes(bytes)
+}
+
+/// Decompresses a gzip stream.
+fn gunzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 4);
+    GzDecoder::new(data).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    enum Fixture {
+        File(&'static [u8]),
+        Group(Vec<(&'static str, Fixture)>),
+    }
+
+    /// Writes an uncompressed group the way c4group does.
+    fn build(entries: &[(&str, Fixture)]) -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE];
+        header[..GROUP_ID.len()].copy_from_slice(GROUP_ID);
+        header[36..40].copy_from_slice(&(entries.len() as i32).to_le_bytes());
+        unscramble(&mut header);
+        let mut cores = Vec::new();
+        let mut contents = Vec::new();
+        for (name, entry) in entries {
+            let (data, child_group) = match entry {
+                Fixture::File(data) => (data.to_vec(), 0i32),
+                Fixture::Group(entries) => (build(entries), 1),
+            };
+            let mut core = vec![0; ENTRY_CORE_SIZE];
+            core[..name.len()].copy_from_slice(name.as_bytes());
+            core[264..268].copy_from_slice(&child_group.to_le_bytes());
+            core[268..272].copy_from_slice(&(data.len() as i32).to_le_bytes());
+            core[276..280].copy_from_slice(&(contents.len() as i32).to_le_bytes());
+            cores.extend(core);
+            contents.extend(data);
+        }
+        [header, cores, contents].concat()
+    }
+
+    /// Compresses a group with the c4group magic.
+    fn pack(group: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(group).unwrap();
+        let mut packed = encoder.finish().unwrap();
+        packed[0] = 0x1e;
+        packed[1] = 0x8c;
+        packed
+    }
+
+    fn file<'a>(group: &'a Group, path: &str) -> Option<&'a [u8]> {
+        match group.get(Path::new(path)) {
+            Some(Entry::File(contents)) => contents.as_deref(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reads_packed_groups() {
+        let group = build(&[
+            ("Rock.ocd", Fixture::Group(vec![
+                ("DefCore.txt", Fixture::File(b"[DefCore]\nid=Rock\n")),
+                ("Script.c", Fixture::File(b"func Hit() {}\n")),
+                ("Graphics.png", Fixture::File(b"\x89PNG")),
+            ])),
+            ("System.ocg", Fixture::Group(vec![])),
+        ]);
+        let path = std::env::temp_dir().join(format!("oclsp-test-{}.ocd", std::process::id()));
+        fs::write(&path, pack(&group)).unwrap();
+        let group = Group::open(&path);
+        fs::remove_file(&path).unwrap();
+        let group = group.unwrap();
+
+        assert_eq!(group.entries.keys().collect::<Vec<_>>(), ["Rock.ocd", "System.ocg"]);
+        assert_eq!(file(&group, "Rock.ocd/Script.c"), Some(&b"func Hit() {}\n"[..]));
+        // Names are case-insensitive.
+        assert_eq!(file(&group, "rock.ocd/defcore.txt"), Some(&b"[DefCore]\nid=Rock\n"[..]));
+        // Only text files are kept in memory.
+        assert!(matches!(group.get(Path::new("Rock.ocd/Graphics.png")), Some(Entry::File(None))));
+        assert!(matches!(group.get(Path::new("System.ocg")), Some(Entry::Group(g)) if g.entries.is_empty()));
+        assert!(group.get(Path::new("Rock.ocd/Script.c/Foo")).is_none());
+        assert!(group.get(Path::new("../Rock.ocd")).is_none());
+    }
+
+    #[test]
+    fn reads_packed_child_groups() {
+        let child = pack(&build(&[("Script.c", Fixture::File(b"#include Rock\n"))]));
+        let mut data = build(&[("Pebble.ocd", Fixture::File(b""))]);
+        // Mark the entry as a child group and point it at the packed data.
+        let core = HEADER_SIZE;
+        data[core + 264..core + 268].copy_from_slice(&1i32.to_le_bytes());
+        data[core + 268..core + 272].copy_from_slice(&(child.len() as i32).to_le_bytes());
+        data.extend(child);
+        let group = Group::parse(&data).unwrap();
+        assert_eq!(file(&group, "Pebble.ocd/Script.c"), Some(&b"#include Rock\n"[..]));
+    }
+
+    #[test]
+    fn rejects_broken_groups() {
+        let group = build(&[("Script.c", Fixture::File(b"func f() {}\n"))]);
+        assert!(Group::parse(&group[..HEADER_SIZE - 1]).is_err());
+        assert!(Group::parse(&group[..group.len() - 1]).is_err());
+        assert!(Group::parse(&vec![0; HEADER_SIZE]).is_err());
+        assert!(gunzip(&group).is_err());
+    }
+}
@@ -12,7 +12,6 @@ pub enum DiagnosticSeverity {
 
 /// Position in script where an error or warning occured.
 pub struct DiagnosticPosition {
-    pub file: String,
     pub function: String, /// empty string if outside function
     pub line: u64, /// starting at line 1
     pub column: u64, /// starting at column 1
@@ -24,7 +23,6 @@ impl DiagnosticPosition {
     fn from_c4s(pos: &c4s_diagnostic_position) -> Option<DiagnosticPosition> {
         if pos.valid > 0 {
             Some(DiagnosticPosition {
-                file: unsafe { CStr::from_ptr(pos.file) }.to_string_lossy().to_string(),
                 function: unsafe { CStr::from_ptr(pos.function) }.to_string_lossy().to_string(),
                 line: pos.line,
                 column: pos.column,
@@ -46,32 +44,67 @@ impl DiagnosticPosition {
     }
 }
 
+/// A message from the script engine.
+pub struct Message {
+    pub severity: DiagnosticSeverity,
+    /// Name of the warning as used with `#warning enable/disable`, e.g.
+    /// `empty_if`, taken from the end of the engine's message. None for
+    /// errors and warnings without a name.
+    pub warning_id: Option<String>,
+    pub text: String,
+    pub position: Option<DiagnosticPosition>,
+}
+
+impl Message {
+    fn from_c4s(severity: DiagnosticSeverity, msg: *const c_char, pos: &c4s_diagnostic_position) -> Message {
+        let text = unsafe { CStr::from_ptr(msg) }.to_string_lossy().to_string();
+        let (text, warning_id) = match severity {
+            DiagnosticSeverity::Warning => split_warning_id(text),
+            DiagnosticSeverity::Error => (text, None),
+        };
+        Message {
+            severity,
+            warning_id,
+            text,
+            position: DiagnosticPosition::from_c4s(pos),
+        }
+    }
+}
+
+/// Takes the name the engine appends to warning messages, as in
+/// `empty controlled statement found [empty_if]`.
+fn split_warning_id(mut text: String) -> (String, Option<String>) {
+    let id = text.strip_suffix(']')
+        .and_then(|rest| rest.rsplit_once(" ["))
+        .map(|(_, id)| id)
+        .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'))
+        .map(str::to_string);
+    if let Some(id) = &id {
+        text.truncate(text.len() - id.len() - 3);
+    }
+    (text, id)
+}
+
 struct DiagnosticsCtx<'a> {
-    diagnostic_fn: &'a mut dyn FnMut(DiagnosticSeverity, String, Option<DiagnosticPosition>),
+    diagnostic_fn: &'a mut dyn FnMut(Message),
 }
 
 extern "C" fn handle_error(ctx: *mut c_void, msg: *const c_char, pos: c4s_diagnostic_position) {
     let ctx = unsafe { &mut *(ctx as *mut DiagnosticsCtx) };
-    let msg = unsafe { CStr::from_ptr(msg) };
-    (ctx.diagnostic_fn)(DiagnosticSeverity::Error,
-        msg.to_string_lossy().to_string(),
-        DiagnosticPosition::from_c4s(&pos));
+    (ctx.diagnostic_fn)(Message::from_c4s(DiagnosticSeverity::Error, msg, &pos));
 }
 
 extern "C" fn handle_warning(ctx: *mut c_void, msg: *const c_char, pos: c4s_diagnostic_position) {
     let ctx = unsafe { &mut *(ctx as *mut DiagnosticsCtx) };
-    let msg = unsafe { CStr::from_ptr(msg) };
-    (ctx.diagnostic_fn)(DiagnosticSeverity::Warning,
-        msg.to_string_lossy().to_string(),
-        DiagnosticPosition::from_c4s(&pos));
+    (ctx.diagnostic_fn)(Message::from_c4s(DiagnosticSeverity::Warning, msg, &pos));
 }
 
-/// Checks a script from a string, returning the number of errors.
-/// For each error and warning message, the given functions are called.
+/// Checks a script from a string, returning the number of errors. For each
+/// error and warning message, the given function is called.
 pub fn check_string<'a, F: 'a>(script: &str, mut diagnostic_fn: F) -> i32
-where F: FnMut(DiagnosticSeverity, String, Option<DiagnosticPosition>) {
-    // TODO: Error handling (NUL bytes)
-    let c_script = CString::new(script).expect("CString::new failed");
+where F: FnMut(Message) {
+    // Scripts can't contain NUL bytes, so dropping them doesn't lose anything.
+    let c_script = CString::new(script.replace('\0', "")).unwrap();
     let ctx = DiagnosticsCtx { diagnostic_fn: &mut diagnostic_fn };
     let mut handlers = c4s_errorhandlers {
         errors: Some(handle_error),
@@ -82,3 +115,22 @@ where F: FnMut(DiagnosticSeverity, String, Option<DiagnosticPosition>) {
         c4script_sys::c4s_checkstring(c_script.as_ptr(), &mut handlers as *mut c4s_errorhandlers)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_warning_ids() {
+        let (text, id) = split_warning_id("empty controlled statement found (use '{}' if this is intentional) [empty_if]".to_string());
+        assert_eq!(text, "empty controlled statement found (use '{}' if this is intentional)");
+        assert_eq!(id.as_deref(), Some("empty_if"));
+    }
+
+    #[test]
+    fn keeps_messages_without_warning_id() {
+        for msg in &["unknown identifier: x", "index [x] out of range", "trailing []"] {
+            assert_eq!(split_warning_id(msg.to_string()), (msg.to_string(), None));
+        }
+    }
+}
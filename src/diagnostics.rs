@@ -0,0 +1,229 @@
+//! Runs the script checker on a background thread.
+//!
+//! Checks are debounced per document: a new version replaces a pending check
+//! of the previous one, and results for versions that were superseded while
+//! the check was running are dropped.
+
+use crate::c4script;
+use crate::document::Document;
+use crate::parser;
+use crate::scope;
+use crate::utils::LineIndex;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use log::trace;
+use lsp_server::{Message, Notification};
+use lsp_types::*;
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time to wait for further changes before checking a document.
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+// Codes of diagnostics that have quick fixes.
+pub const UNKNOWN_IDENTIFIER: &str = "unknown_identifier";
+pub const UNKNOWN_FUNCTION: &str = "unknown_function";
+pub const MISSING_SEMICOLON: &str = "missing_semicolon";
+pub const UNUSED_PARAMETER: &str = "unused_parameter";
+
+enum Task {
+    Check {
+        uri: Url,
+        document: Document,
+        due: Instant,
+    },
+    Close(Url),
+}
+
+pub struct Checker {
+    tasks: Sender<Task>,
+}
+
+impl Checker {
+    /// Starts the worker thread, which publishes diagnostics via `output`.
+    pub fn new(output: Sender<Message>) -> Checker {
+        let (tasks, receiver) = crossbeam_channel::unbounded();
+        thread::spawn(move || Worker {
+            receiver,
+            output,
+            pending: HashMap::new(),
+            documents: HashMap::new(),
+        }.run());
+        Checker { tasks }
+    }
+
+    /// Schedules a check of the document after `delay`.
+    pub fn check(&self, uri: Url, document: Document, delay: Duration) {
+        let due = Instant::now() + delay;
+        let _ = self.tasks.send(Task::Check { uri, document, due });
+    }
+
+    /// Cancels pending checks and clears the diagnostics of a closed document.
+    pub fn close(&self, uri: Url) {
+        let _ = self.tasks.send(Task::Close(uri));
+    }
+}
+
+struct Worker {
+    receiver: Receiver<Task>,
+    output: Sender<Message>,
+    /// Documents waiting to be checked, with their due times.
+    pending: HashMap<Url, Instant>,
+    /// Latest contents of the open documents.
+    documents: HashMap<Url, Document>,
+}
+
+impl Worker {
+    fn run(mut self) {
+        loop {
+            let next_due = self.pending.values().min().copied();
+            let task = match next_due {
+                Some(due) => self.receiver.recv_timeout(due.saturating_duration_since(Instant::now())),
+                None => self.receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match task {
+                Ok(task) => {
+                    self.handle(task);
+                    continue;
+                },
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            let now = Instant::now();
+            let uri = match self.pending.iter().find(|(_, due)| **due <= now) {
+                Some((uri, _)) => uri.clone(),
+                None => continue,
+            };
+            self.pending.remove(&uri);
+            let (code, version) = match self.documents.get(&uri) {
+                Some(doc) => (doc.text(), doc.version),
+                None => continue,
+            };
+            let diagnostics = check(&code);
+
+            // Changes that arrived in the meantime make the result obsolete.
+            while let Ok(task) = self.receiver.try_recv() {
+                self.handle(task);
+            }
+            if self.documents.get(&uri).map(|doc| doc.version) == Some(version) {
+                self.publish(uri, diagnostics, version);
+            }
+        }
+    }
+
+    fn handle(&mut self, task: Task) {
+        match task {
+            Task::Check { uri, document, due } => {
+                self.documents.insert(uri.clone(), document);
+                self.pending.insert(uri, due);
+            },
+            Task::Close(uri) => {
+                self.pending.remove(&uri);
+                self.documents.remove(&uri);
+                self.publish(uri, Vec::new(), None);
+            },
+        }
+    }
+
+    fn publish(&self, uri: Url, diagnostics: Vec<Diagnostic>, version: Option<i64>) {
+        let notification = Notification::new(
+            "textDocument/publishDiagnostics".into(),
+            PublishDiagnosticsParams { uri, diagnostics, version },
+        );
+        trace!("Sending notification: {:#?}", notification);
+        let _ = self.output.send(Message::Notification(notification));
+    }
+}
+
+/// Checks a script, adding the diagnostics the engine doesn't give.
+fn check(code: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    c4script::check_string(code, |msg| {
+        let c4script::Message { severity, warning_id, text, position: pos } = msg;
+        // Warnings are named by the engine, errors by their message.
+        let code = warning_id
+            .or_else(|| message_code(&text).map(|code| code.to_string()))
+            .map(NumberOrString::String);
+        let message = match &pos {
+            Some(p) if !p.function.is_empty() => format!("{} (in {})", text, p.function),
+            _ => text,
+        };
+        diagnostics.push(Diagnostic {
+            range: if let Some(p) = pos {
+                p.to_range()
+            } else {
+                Range::default()
+            },
+            severity: Some(match severity {
+                c4script::DiagnosticSeverity::Error   => DiagnosticSeverity::Error,
+                c4script::DiagnosticSeverity::Warning => DiagnosticSeverity::Warning,
+            }),
+            code,
+            message,
+            ..Diagnostic::default()
+        })
+    });
+    diagnostics.extend(lint(code));
+    diagnostics
+}
+
+/// Classifies engine errors by their text, for messages the engine doesn't
+/// give an ID. The prefixes are the format strings of the errors thrown in
+/// C4AulParse.cpp and C4AulCompiler.cpp.
+pub fn message_code(message: &str) -> Option<&'static str> {
+    if message.starts_with("unknown identifier") {
+        Some(UNKNOWN_IDENTIFIER)
+    } else if message.starts_with("called function not found") || message.starts_with("unknown function") {
+        Some(UNKNOWN_FUNCTION)
+    } else if message.starts_with("';' expected") {
+        Some(MISSING_SEMICOLON)
+    } else {
+        None
+    }
+}
+
+/// Hints the engine doesn't give.
+fn lint(code: &str) -> Vec<Diagnostic> {
+    let script = parser::parse(code);
+    let index = LineIndex::new(code);
+    scope::unused_parameters(&script, code).into_iter()
+        .map(|param| Diagnostic {
+            range: index.range(param.span),
+            severity: Some(DiagnosticSeverity::Hint),
+            code: Some(NumberOrString::String(UNUSED_PARAMETER.to_string())),
+            message: format!("parameter \"{}\" is never used", param.name),
+            tags: Some(vec![DiagnosticTag::Unnecessary]),
+            ..Diagnostic::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_engine_errors() {
+        // As formatted by the engine, e.g. `UnexpectedToken` for `';'`.
+        let messages = [
+            ("unknown identifier: Foo", Some(UNKNOWN_IDENTIFIER)),
+            ("called function not found: Foo", Some(UNKNOWN_FUNCTION)),
+            ("unknown function: Foo", Some(UNKNOWN_FUNCTION)),
+            ("';' expected, but found identifier", Some(MISSING_SEMICOLON)),
+            ("')' expected, but found ';'", None),
+            ("empty controlled statement found (use '{}' if this is intentional)", None),
+        ];
+        for (message, code) in &messages {
+            assert_eq!(message_code(message), *code, "{}", message);
+        }
+    }
+
+    #[test]
+    fn attaches_codes_to_lints() {
+        let diagnostics = lint("func f(a, b)\n{\n\treturn b;\n}\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Some(NumberOrString::String(UNUSED_PARAMETER.to_string())));
+        assert_eq!(diagnostics[0].range.start, Position::new(0, 7));
+    }
+}
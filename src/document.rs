@@ -0,0 +1,95 @@
+//! Open documents, stored as ropes so that incremental changes are cheap.
+
+use lsp_types::{Position, TextDocumentContentChangeEvent};
+use ropey::Rope;
+
+#[derive(Clone)]
+pub struct Document {
+    pub version: Option<i64>,
+    rope: Rope,
+}
+
+impl Document {
+    pub fn new(text: &str, version: Option<i64>) -> Document {
+        Document { version, rope: Rope::from_str(text) }
+    }
+
+    pub fn text(&self) -> String {
+        self.rope.to_string()
+    }
+
+    /// Applies a change sent by the client. Changes without a range replace
+    /// the whole document.
+    pub fn apply(&mut self, change: &TextDocumentContentChangeEvent) {
+        match change.range {
+            Some(range) => {
+                let start = self.char_index(range.start);
+                let end = self.char_index(range.end).max(start);
+                self.rope.remove(start..end);
+                self.rope.insert(start, &change.text);
+            },
+            None => self.rope = Rope::from_str(&change.text),
+        }
+    }
+
+    /// Converts an LSP position with its UTF-16 column to a char index,
+    /// clamping positions outside of the document. Columns past the end of a
+    /// line end up before the line break.
+    fn char_index(&self, pos: Position) -> usize {
+        let line = pos.line as usize;
+        if line >= self.rope.len_lines() {
+            return self.rope.len_chars();
+        }
+        let line_start = self.rope.line_to_char(line);
+        let text = self.rope.line(line);
+        let mut len = text.len_chars();
+        if len > 0 && text.char(len - 1) == '\n' {
+            len -= 1;
+        }
+        if len > 0 && text.char(len - 1) == '\r' {
+            len -= 1;
+        }
+        let utf16_start = self.rope.char_to_utf16_cu(line_start);
+        let utf16_end = self.rope.char_to_utf16_cu(line_start + len);
+        let utf16 = (utf16_start + pos.character as usize).min(utf16_end);
+        self.rope.utf16_cu_to_char(utf16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::Range;
+
+    fn edit(text: &str, start: (u64, u64), end: (u64, u64), new_text: &str) -> String {
+        let mut doc = Document::new(text, None);
+        doc.apply(&TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position { line: start.0, character: start.1 },
+                end: Position { line: end.0, character: end.1 },
+            }),
+            range_length: None,
+            text: new_text.to_string(),
+        });
+        doc.text()
+    }
+
+    #[test]
+    fn counts_utf16_columns() {
+        assert_eq!(edit("ä😀b\n", (0, 3), (0, 4), "c"), "ä😀c\n");
+    }
+
+    #[test]
+    fn clamps_columns_to_the_line() {
+        assert_eq!(edit("ab\ncd\n", (0, 10), (0, 10), "X"), "abX\ncd\n");
+        assert_eq!(edit("ab\r\ncd\r\n", (0, 10), (0, 10), "X"), "abX\r\ncd\r\n");
+        assert_eq!(edit("ab\ncd", (1, 10), (5, 0), "X"), "ab\ncdX");
+    }
+
+    #[test]
+    fn breaks_lines_at_lf_cr_and_crlf() {
+        assert_eq!(edit("a\u{c}b\nc", (1, 0), (1, 0), "X"), "a\u{c}b\nXc");
+        assert_eq!(edit("a\u{2028}b\rc\nd", (1, 1), (1, 1), "X"), "a\u{2028}b\rcX\nd");
+        assert_eq!(edit("a\rb\r\nc", (0, 5), (2, 0), "X"), "aXc");
+    }
+}
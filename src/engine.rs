@@ -0,0 +1,144 @@
+//! Functions provided by the engine, collected from the OpenClonk
+//! documentation at build time.
+
+/// An engine function as described in the script reference.
+pub struct Function {
+    pub name: &'static str,
+    pub return_type: &'static str,
+    pub params: &'static [Param],
+    /// Description in Markdown.
+    pub description: &'static str,
+    pub examples: &'static [Example],
+}
+
+pub struct Param {
+    pub ty: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub optional: bool,
+}
+
+pub struct Example {
+    pub code: &'static str,
+    pub text: &'static str,
+}
+
+include!(concat!(env!("OUT_DIR"), "/engine_functions.rs"));
+
+impl Function {
+    /// Declaration of the function, e.g. `object CreateObject(id definition, int x)`.
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self.params.iter()
+            .map(|p| format!("{} {}", p.ty, p.name).trim().to_string())
+            .collect();
+        format!("{} {}({})", self.return_type, self.name, params.join(", ")).trim().to_string()
+    }
+
+    /// Full documentation in Markdown.
+    pub fn documentation(&self) -> String {
+        let mut doc = format!("```c4script\n{}\n```\n\n{}\n", self.signature(), self.description);
+        if !self.params.is_empty() {
+            doc.push_str("\n**Parameters**\n\n");
+            for p in self.params {
+                let optional = if p.optional { " (optional)" } else { "" };
+                doc.push_str(&format!("- `{}`{}: {}\n", p.name, optional, p.description));
+            }
+        }
+        for example in self.examples {
+            doc.push_str(&format!("\n**Example**\n\n```c4script\n{}\n```\n\n{}\n", example.code, example.text));
+        }
+        doc
+    }
+}
+
+/// Functions the engine calls on objects and scenarios.
+pub const CALLBACKS: &[&str] = &[
+    "Construction", "Initialize", "Destruction", "Death", "Damage", "Hit", "Hit2", "Hit3",
+    "Entrance", "Departure", "Collection", "Collection2", "Ejection", "RejectCollect",
+    "RejectEntrance", "ContentsDestruction", "Incineration", "IncinerationEx", "Extinguishing",
+    "Contact", "ContactLeft", "ContactRight", "ContactTop", "ContactBottom", "CatchBlow",
+    "QueryCatchBlow", "OnHit", "Grab", "Grabbed", "Purchase", "Sale", "Recruitment",
+    "LineBreak", "ControlUse", "ControlUseStart", "ControlUseHolding", "ControlUseStop",
+    "ControlUseCancel", "Selection", "Deselection", "SaveScenarioObject", "OnSynchronized",
+    "InitializePlayer", "RemovePlayer", "RelaunchPlayer", "InitializeScriptPlayer",
+    "InitializeObjects", "InitializeAmbience", "OnGameOver", "OnClonkDeath", "OnClonkRecruitment",
+    "PlayerControl", "PlayerControlRelease", "OnActionChanged", "Definition",
+];
+
+/// Functions that take the name of a function to call as a string, e.g.
+/// `Call("Foo")` or `Schedule(obj, "Foo()", 10)`.
+pub const DYNAMIC_CALLS: &[&str] = &[
+    "Call", "PrivateCall", "ProtectedCall", "GameCall", "GameCallEx", "DefinitionCall",
+    "Schedule", "ScheduleCall", "AddTimer", "RemoveTimer",
+];
+
+/// Functions of older Clonk versions that were replaced, with their
+/// successors.
+const DEPRECATED: &[(&str, &str)] = &[
+    ("FindObject2", "FindObject"),
+    ("ObjectCount2", "ObjectCount"),
+];
+
+/// Returns the function replacing a deprecated one.
+pub fn successor(name: &str) -> Option<&'static str> {
+    DEPRECATED.iter().find(|(old, _)| *old == name).map(|(_, new)| *new)
+}
+
+/// Effect callbacks are named `Fx<Effect><Callback>`.
+const EFFECT_CALLBACKS: &[&str] = &["Start", "Timer", "Stop", "Effect", "Damage", "Info"];
+
+/// Whether the engine calls a function of this name by itself.
+pub fn is_callback(name: &str) -> bool {
+    CALLBACKS.contains(&name)
+        || (name.starts_with("Fx") && EFFECT_CALLBACKS.iter().any(|cb| name.len() > 2 + cb.len() && name.ends_with(cb)))
+}
+
+/// Looks up an engine function by name.
+pub fn function(name: &str) -> Option<&'static Function> {
+    ENGINE_FUNCTIONS.binary_search_by_key(&name, |f| f.name).ok().map(|i| &ENGINE_FUNCTIONS[i])
+}
+
+pub fn is_engine_function(name: &str) -> bool {
+    function(name).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CREATE_OBJECT: Function = Function {
+        name: "CreateObject",
+        return_type: "object",
+        params: &[
+            Param { ty: "id", name: "type", description: "Type of the object.", optional: false },
+            Param { ty: "int", name: "x", description: "Horizontal offset.", optional: true },
+        ],
+        description: "Creates an object.",
+        examples: &[Example { code: "CreateObject(Rock);", text: "Creates a rock." }],
+    };
+
+    #[test]
+    fn documents_signature_parameters_and_examples() {
+        assert_eq!(CREATE_OBJECT.signature(), "object CreateObject(id type, int x)");
+        assert_eq!(CREATE_OBJECT.documentation(), "```c4script\nobject CreateObject(id type, int x)\n```\n\n\
+            Creates an object.\n\n\
+            **Parameters**\n\n\
+            - `type`: Type of the object.\n\
+            - `x` (optional): Horizontal offset.\n\n\
+            **Example**\n\n```c4script\nCreateObject(Rock);\n```\n\nCreates a rock.\n");
+    }
+
+    #[test]
+    fn leaves_out_missing_types() {
+        let f = Function { name: "Foo", return_type: "", params: &[Param { ty: "", name: "x", description: "", optional: false }], description: "", examples: &[] };
+        assert_eq!(f.signature(), "Foo(x)");
+    }
+
+    #[test]
+    fn recognizes_effect_callbacks() {
+        assert!(is_callback("Hit"));
+        assert!(is_callback("FxBurnTimer"));
+        assert!(!is_callback("FxTimer"));
+        assert!(!is_callback("Foo"));
+    }
+}
@@ -0,0 +1,98 @@
+//! Requests that lsp-types doesn't know about: our own extensions and newer
+//! parts of the protocol.
+
+use lsp_types::request::Request;
+use lsp_types::{Location, Range, TextDocumentIdentifier, TextDocumentPositionParams, Url};
+use serde::{Deserialize, Serialize};
+
+/// Returns the content of a script inside a packed group, so that clients
+/// can show `c4group:` URIs.
+pub enum GroupContent {}
+
+impl Request for GroupContent {
+    type Params = TextDocumentIdentifier;
+    type Result = Option<String>;
+    const METHOD: &'static str = "oclsp/groupContent";
+}
+
+/// A result of `textDocument/references`. Clients that don't know the extra
+/// field see a plain location.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceLocation {
+    #[serde(flatten)]
+    pub location: Location,
+    /// Set for lower-confidence matches that only call the function at
+    /// runtime, e.g. `obj->~Foo()` or `Call("Foo")`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dynamic: bool,
+}
+
+// Semantic tokens, from protocol version 3.16.
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensRangeParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SemanticTokens {
+    pub data: Vec<u32>,
+}
+
+pub enum SemanticTokensRequest {}
+
+impl Request for SemanticTokensRequest {
+    type Params = SemanticTokensParams;
+    type Result = SemanticTokens;
+    const METHOD: &'static str = "textDocument/semanticTokens/full";
+}
+
+pub enum SemanticTokensRangeRequest {}
+
+impl Request for SemanticTokensRangeRequest {
+    type Params = SemanticTokensRangeParams;
+    type Result = SemanticTokens;
+    const METHOD: &'static str = "textDocument/semanticTokens/range";
+}
+
+/// Describes how a script is combined with others through `#include` and
+/// `#appendto`.
+pub enum InheritanceRequest {}
+
+impl Request for InheritanceRequest {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Inheritance>;
+    const METHOD: &'static str = "oclsp/inheritance";
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inheritance {
+    /// ID of the definition the script belongs to.
+    pub id: Option<String>,
+    /// Definitions included by the definition's scripts.
+    pub includes: Vec<InheritanceItem>,
+    /// Scripts appending to the definition.
+    pub appendtos: Vec<InheritanceItem>,
+    /// All scripts making up the definition. Functions in earlier scripts
+    /// override those in later ones.
+    pub override_order: Vec<InheritanceItem>,
+    /// Where `inherited` in the function at the given position leads to.
+    pub inherited: Option<Location>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InheritanceItem {
+    /// Definition ID or name of the script.
+    pub name: String,
+    pub uri: Url,
+}
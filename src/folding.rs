@@ -0,0 +1,114 @@
+//! Folding ranges for `textDocument/foldingRange`.
+
+use crate::ast::*;
+use crate::lexer::{self, Span};
+use crate::utils::LineIndex;
+use lsp_types::{FoldingRange, FoldingRangeKind};
+use regex::Regex;
+
+/// Folds functions, blocks, multi-line proplists and arrays, block comments
+/// and the regions below `/*-- Section --*/` comments.
+pub fn folding_ranges(script: &Script, code: &str) -> Vec<FoldingRange> {
+    let index = LineIndex::new(code);
+    let mut ranges = Vec::new();
+    // The closing bracket stays visible.
+    let mut push_block = |span: Span| {
+        let start = index.position(span.start).line;
+        let end = index.position(span.end).line;
+        if end > start + 1 {
+            ranges.push(fold(start, end - 1, None));
+        }
+    };
+    let mut bodies = Vec::new();
+    script.walk(&mut |node| match node {
+        // Functions fold from their header, not from the brace on the next line.
+        Node::Function(f) => {
+            push_block(f.span.to(f.body.span));
+            bodies.push(f.body.span);
+        },
+        Node::Block(b) if !bodies.contains(&b.span) => push_block(b.span),
+        Node::Expr(Expr { kind: ExprKind::Proplist { .. }, span })
+            | Node::Expr(Expr { kind: ExprKind::Array(_), span }) => push_block(*span),
+        _ => (),
+    });
+
+    for comment in &script.comments {
+        let start = index.position(comment.start).line;
+        let end = index.position(comment.end).line;
+        if end > start && comment.text(code).starts_with("/*") {
+            ranges.push(fold(start, end, Some(FoldingRangeKind::Comment)));
+        }
+    }
+
+    // A section reaches up to the next one, without trailing blank lines.
+    let section_re = Regex::new(lexer::SECTION_HEADER).unwrap();
+    let nodes = script.nodes();
+    let sections: Vec<&Span> = script.comments.iter()
+        .filter(|c| !nodes.iter().any(|n| n.span().start < c.start && c.end <= n.span().end))
+        .filter(|c| section_re.is_match(c.text(code)))
+        .collect();
+    for (i, section) in sections.iter().enumerate() {
+        let next = sections.get(i + 1).map_or(code.len(), |s| s.start);
+        let start = index.position(section.start).line;
+        let end = index.position(code[..next].trim_end().len()).line;
+        if end > start {
+            ranges.push(fold(start, end, Some(FoldingRangeKind::Region)));
+        }
+    }
+
+    ranges.sort_by_key(|r| (r.start_line, std::cmp::Reverse(r.end_line)));
+    ranges.dedup_by_key(|r| r.start_line);
+    ranges
+}
+
+fn fold(start_line: u64, end_line: u64, kind: Option<FoldingRangeKind>) -> FoldingRange {
+    FoldingRange { start_line, end_line, kind, ..FoldingRange::default() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn folds(code: &str) -> Vec<(u64, u64, Option<FoldingRangeKind>)> {
+        folding_ranges(&parser::parse(code), code).into_iter()
+            .map(|r| (r.start_line, r.end_line, r.kind))
+            .collect()
+    }
+
+    #[test]
+    fn folds_code_comments_and_sections() {
+        let code = "/*-- Setup --*/\n\
+            \n\
+            /* A long\n\
+            comment */\n\
+            func Initialize()\n\
+            {\n\
+            \tif (true)\n\
+            \t{\n\
+            \t\tLog(\"x\");\n\
+            \t}\n\
+            \tvar props = {\n\
+            \t\ta = 1,\n\
+            \t};\n\
+            }\n\
+            \n\
+            /*-- Callbacks --*/\n\
+            \n\
+            func Hit() { return; }\n\
+            \n";
+        assert_eq!(folds(code), [
+            (0, 13, Some(FoldingRangeKind::Region)),
+            (2, 3, Some(FoldingRangeKind::Comment)),
+            (4, 12, None),
+            (7, 8, None),
+            (10, 11, None),
+            (15, 17, Some(FoldingRangeKind::Region)),
+        ]);
+    }
+
+    #[test]
+    fn skips_blocks_without_lines_to_hide() {
+        assert_eq!(folds("func f() { return; }\nfunc g() {\n}\nlocal a = [\n];\n"), []);
+    }
+}
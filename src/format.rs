@@ -0,0 +1,482 @@
+//! Code formatter following the OpenClonk code style.
+//!
+//! The formatter works on the token stream instead of the syntax tree so that
+//! comments and the user's line breaks survive. It re-indents with tabs, puts
+//! block braces on their own lines, normalizes spacing around operators and
+//! cleans up `/*-- Section --*/` headers.
+
+use crate::lexer::{self, Token, TokenKind};
+use crate::utils::LineIndex;
+use lsp_types::{Range, TextEdit};
+use regex::Regex;
+
+const KEYWORDS: &[&str] = &[
+    "if", "else", "while", "for", "do", "return", "var", "local", "static", "const", "func",
+    "public", "protected", "private", "global", "in", "new", "break", "continue",
+];
+
+/// Keywords that take a parenthesized header followed by a body.
+const HEADER_KEYWORDS: &[&str] = &["if", "while", "for"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FrameKind {
+    Root,
+    Block,
+    /// A block whose braces are on a single line, e.g. `{ return true; }`.
+    InlineBlock,
+    Proplist,
+    Paren,
+    Bracket,
+}
+
+struct Frame {
+    kind: FrameKind,
+    /// Indentation of the line the frame was opened on.
+    open_indent: usize,
+    /// Indentation of the frame's contents.
+    indent: usize,
+    /// Extra indentation for bodies of `if`, `else` etc. without braces.
+    hang: usize,
+    /// Set after a header like `if (...)` until the body starts.
+    pending_hang: bool,
+    /// Whether this is the parenthesized header of `if`, `while` or `for`.
+    header: bool,
+}
+
+impl Frame {
+    fn is_block(&self) -> bool {
+        self.kind == FrameKind::Root || self.kind == FrameKind::Block || self.kind == FrameKind::InlineBlock
+    }
+}
+
+fn is_keyword(text: &str) -> bool {
+    KEYWORDS.contains(&text)
+}
+
+/// Formats a whole script.
+pub fn format(code: &str) -> String {
+    Formatter::new(code).run()
+}
+
+/// Returns the edits that turn `old` into `new`, one per changed group of lines.
+pub fn text_edits(old: &str, new: &str) -> Vec<TextEdit> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let prefix = old_lines.iter().zip(&new_lines).take_while(|(a, b)| a == b).count();
+    let suffix = old_lines[prefix..].iter().rev().zip(new_lines[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b).count();
+    let a = &old_lines[prefix..old_lines.len() - suffix];
+    let b = &new_lines[prefix..new_lines.len() - suffix];
+
+    // Pairs of (old line, new line) that stay unchanged, via longest common subsequence.
+    let mut common = Vec::new();
+    if a.len() * b.len() <= 4_000_000 {
+        let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            if a[i] == b[j] {
+                common.push((i, j));
+                i += 1;
+                j += 1;
+            } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+    common.push((a.len(), b.len()));
+
+    let mut line_starts = vec![0];
+    for line in &old_lines {
+        line_starts.push(line_starts.last().unwrap() + line.len());
+    }
+    let index = LineIndex::new(old);
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (ci, cj) in common {
+        if ci > i || cj > j {
+            edits.push(TextEdit {
+                range: Range {
+                    start: index.position(line_starts[prefix + i]),
+                    end: index.position(line_starts[prefix + ci]),
+                },
+                new_text: b[j..cj].concat(),
+            });
+        }
+        i = ci + 1;
+        j = cj + 1;
+    }
+    edits
+}
+
+/// Splits text into lines, keeping the line terminators.
+fn split_lines(text: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, _) in text.match_indices('\n') {
+        lines.push(&text[start..=i]);
+        start = i + 1;
+    }
+    if start < text.len() {
+        lines.push(&text[start..]);
+    }
+    lines
+}
+
+struct Formatter<'a> {
+    code: &'a str,
+    tokens: Vec<Token>,
+    /// Index of the matching closing bracket for each opening bracket.
+    matching: Vec<Option<usize>>,
+    newline: &'static str,
+    section_re: Regex,
+    out: String,
+    stack: Vec<Frame>,
+    line_indent: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(code: &'a str) -> Formatter<'a> {
+        let tokens = lexer::tokenize(code);
+        let mut matching = vec![None; tokens.len()];
+        let mut open: Vec<usize> = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            match token.span.text(code) {
+                "{" | "(" | "[" => open.push(i),
+                close @ "}" | close @ ")" | close @ "]" => {
+                    let expected = match close { "}" => "{", ")" => "(", _ => "[" };
+                    if open.last().map_or(false, |&o| tokens[o].span.text(code) == expected) {
+                        matching[open.pop().unwrap()] = Some(i);
+                    }
+                },
+                _ => (),
+            }
+        }
+        Formatter {
+            code,
+            tokens,
+            matching,
+            newline: if code.contains("\r\n") { "\r\n" } else { "\n" },
+            section_re: Regex::new(lexer::SECTION_HEADER).unwrap(),
+            out: String::new(),
+            stack: vec![Frame { kind: FrameKind::Root, open_indent: 0, indent: 0, hang: 0, pending_hang: false, header: false }],
+            line_indent: 0,
+        }
+    }
+
+    fn text(&self, i: usize) -> &'a str {
+        self.tokens[i].span.text(self.code)
+    }
+
+    /// Previous token that isn't a comment.
+    fn prev_significant(&self, i: usize) -> Option<usize> {
+        (0..i).rev().find(|&j| !self.tokens[j].is_comment())
+    }
+
+    fn next_significant(&self, i: usize) -> Option<usize> {
+        (i + 1..self.tokens.len()).find(|&j| !self.tokens[j].is_comment())
+    }
+
+    fn is_operand(&self, i: usize) -> bool {
+        let token = self.tokens[i];
+        match token.kind {
+            TokenKind::Ident => !is_keyword(self.text(i)),
+            TokenKind::Number | TokenKind::String => true,
+            _ => matches!(self.text(i), ")" | "]" | "}" | "..." | "$"),
+        }
+    }
+
+    fn is_section_header(&self, i: usize) -> bool {
+        self.tokens[i].kind == TokenKind::BlockComment && self.section_re.is_match(self.text(i))
+    }
+
+    /// Whether the `{` at index i starts a code block rather than a proplist.
+    fn is_block_brace(&self, i: usize) -> bool {
+        match self.prev_significant(i) {
+            None => true,
+            Some(p) => self.tokens[p].kind == TokenKind::Directive
+                || matches!(self.text(p), ")" | "else" | "do" | ";" | "{" | "}"),
+        }
+    }
+
+    fn is_single_line(&self, i: usize) -> bool {
+        match self.matching[i] {
+            Some(j) => !self.code[self.tokens[i].span.start..self.tokens[j].span.end].contains('\n'),
+            None => false,
+        }
+    }
+
+    /// The kind of frame an opening bracket at index i creates.
+    fn frame_kind(&self, i: usize) -> FrameKind {
+        match self.text(i) {
+            "(" => FrameKind::Paren,
+            "[" => FrameKind::Bracket,
+            _ if !self.is_block_brace(i) => FrameKind::Proplist,
+            _ if self.is_single_line(i) => FrameKind::InlineBlock,
+            _ => FrameKind::Block,
+        }
+    }
+
+    fn top(&mut self) -> &mut Frame {
+        self.stack.last_mut().unwrap()
+    }
+
+    /// Whether the token at index i closes the innermost frame.
+    fn closes_top(&self, i: usize) -> bool {
+        let kind = self.stack.last().unwrap().kind;
+        match self.text(i) {
+            "}" => kind == FrameKind::Block || kind == FrameKind::InlineBlock || kind == FrameKind::Proplist,
+            ")" => kind == FrameKind::Paren,
+            "]" => kind == FrameKind::Bracket,
+            _ => false,
+        }
+    }
+
+    fn space_before(&self, i: usize, prev: usize, prev_unary: bool) -> bool {
+        let p = self.text(prev);
+        let cur = self.text(i);
+        if self.tokens[i].is_comment() {
+            return true;
+        }
+        // `$Name$` string table references
+        if (p == "$" && self.tokens[i].kind == TokenKind::Ident && self.text_at(i + 1) == "$")
+            || (cur == "$" && prev > 0 && self.text(prev - 1) == "$")
+        {
+            return false;
+        }
+        if p == "(" || p == "[" || prev_unary || p == "->" || p == "->~" || p == "." {
+            return false;
+        }
+        match cur {
+            ")" | "]" | "," | ";" | ":" | "->" | "->~" | "." => false,
+            "++" | "--" if self.is_operand(prev) => false,
+            "(" => !(self.is_operand(prev) || p == "func"),
+            "[" => !self.is_operand(prev),
+            "}" => p != "{",
+            _ => true,
+        }
+    }
+
+    fn text_at(&self, i: usize) -> &'a str {
+        if i < self.tokens.len() { self.text(i) } else { "" }
+    }
+
+    fn is_prefix_unary(&self, i: usize, prev: Option<usize>) -> bool {
+        match self.text(i) {
+            "!" | "~" => true,
+            "-" | "+" | "++" | "--" => prev.map_or(true, |p| !self.is_operand(p)),
+            _ => false,
+        }
+    }
+
+    fn end_line(&mut self) {
+        let trimmed = self.out.trim_end_matches(&[' ', '\t'][..]).len();
+        self.out.truncate(trimmed);
+        self.out.push_str(self.newline);
+    }
+
+    fn run(mut self) -> String {
+        let mut prev: Option<usize> = None;
+        let mut prev_unary = false;
+        let mut force_newline = false;
+        let mut force_blank = false;
+        for i in 0..self.tokens.len() {
+            let token = self.tokens[i];
+            let text = self.text(i);
+            let gap = &self.code[prev.map_or(0, |p| self.tokens[p].span.end)..token.span.start];
+            let original_newlines = gap.matches('\n').count();
+            let opens = matches!(text, "{" | "(" | "[");
+            let kind = if opens { Some(self.frame_kind(i)) } else { None };
+            let closing = if self.closes_top(i) { Some(self.stack.last().unwrap().kind) } else { None };
+            let section = self.is_section_header(i);
+
+            let mut newline = original_newlines > 0 || force_newline;
+            let mut blank = original_newlines > 1 || force_blank;
+            if kind == Some(FrameKind::Block) || closing == Some(FrameKind::Block)
+                || token.kind == TokenKind::Directive
+            {
+                newline = true;
+            }
+            if section {
+                newline = true;
+                blank = true;
+            }
+            // Keep trailing comments on their line.
+            if token.is_comment() && original_newlines == 0 {
+                newline = false;
+            }
+            let after_block_open = prev.map_or(false, |p| self.text(p) == "{")
+                && self.stack.last().unwrap().kind == FrameKind::Block;
+            if closing == Some(FrameKind::Block) || after_block_open {
+                blank = false;
+            }
+            if prev.is_none() {
+                newline = false;
+                blank = false;
+            }
+            force_newline = false;
+            force_blank = false;
+
+            // The body of a header without braces is indented one more level.
+            if self.top().pending_hang {
+                self.top().pending_hang = false;
+                if kind != Some(FrameKind::Block) && kind != Some(FrameKind::InlineBlock) {
+                    self.top().hang += 1;
+                }
+            }
+
+            if newline {
+                self.end_line();
+                if blank {
+                    self.out.push_str(self.newline);
+                }
+                let frame = self.stack.last().unwrap();
+                let prev_sig = self.prev_significant(i);
+                let continuation = frame.is_block() && kind != Some(FrameKind::Block) && !token.is_comment()
+                    && token.kind != TokenKind::Directive && !section
+                    && prev_sig.map_or(false, |p| self.tokens[p].kind != TokenKind::Directive
+                        && !matches!(self.text(p), ";" | "{" | "}" | ")" | "else" | "do"));
+                self.line_indent = if closing.is_some() {
+                    frame.open_indent
+                } else if frame.is_block() {
+                    frame.indent + frame.hang + if continuation { 1 } else { 0 }
+                } else {
+                    frame.indent
+                };
+                for _ in 0..self.line_indent {
+                    self.out.push('\t');
+                }
+            } else if let Some(p) = prev {
+                if token.is_comment() {
+                    // Preserve alignment of trailing comments.
+                    self.out.push_str(if gap.is_empty() { " " } else { gap });
+                } else if self.space_before(i, p, prev_unary) {
+                    self.out.push(' ');
+                }
+            }
+
+            if section {
+                let title = self.section_re.captures(text).unwrap()[1].to_string();
+                self.out.push_str(&format!("/*-- {} --*/", title));
+                force_newline = true;
+                force_blank = i + 1 < self.tokens.len();
+            } else if token.kind == TokenKind::Directive {
+                self.out.push_str(&text.split_whitespace().collect::<Vec<_>>().join(" "));
+            } else {
+                self.out.push_str(text.trim_end());
+            }
+            if token.kind == TokenKind::LineComment {
+                force_newline = true;
+            }
+
+            // Update the frame stack.
+            if let Some(kind) = kind {
+                let header = text == "(" && self.prev_significant(i).map_or(false, |p| HEADER_KEYWORDS.contains(&self.text(p)));
+                let open_indent = self.line_indent;
+                self.stack.push(Frame { kind, open_indent, indent: open_indent + 1, hang: 0, pending_hang: false, header });
+                if kind == FrameKind::Block {
+                    force_newline = true;
+                }
+            } else if let Some(kind) = closing {
+                let frame = self.stack.pop().unwrap();
+                let next = self.next_significant(i);
+                if frame.header {
+                    self.top().pending_hang = true;
+                } else if (kind == FrameKind::Block || kind == FrameKind::InlineBlock) && self.top().is_block()
+                    && next.map_or(true, |n| self.text(n) != "else")
+                {
+                    self.top().hang = 0;
+                }
+                if kind == FrameKind::Block && next.map_or(true, |n| !matches!(self.text(n), ";" | "," | ")" | "]")) {
+                    force_newline = true;
+                }
+            } else if text == ";" && self.top().is_block() {
+                self.top().hang = 0;
+            } else if text == "do" || (text == "else" && self.next_significant(i).map_or(true, |n| self.text(n) != "if")) {
+                self.top().pending_hang = true;
+            }
+
+            prev_unary = self.is_prefix_unary(i, self.prev_significant(i));
+            prev = Some(i);
+        }
+        if !self.out.is_empty() {
+            self.end_line();
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies edits, which must not overlap, to a text.
+    fn apply(text: &str, edits: &[TextEdit]) -> String {
+        let index = LineIndex::new(text);
+        let mut edits: Vec<(usize, usize, &str)> = edits.iter()
+            .map(|e| (index.offset(e.range.start).unwrap(), index.offset(e.range.end).unwrap(), e.new_text.as_str()))
+            .collect();
+        edits.sort_by_key(|&(start, _, _)| std::cmp::Reverse(start));
+        let mut result = text.to_string();
+        for (start, end, new_text) in edits {
+            result.replace_range(start..end, new_text);
+        }
+        result
+    }
+
+    const MESSY: &str = "/*--Clonk--*/\n#include   Library_Foo\nlocal Name=\"$Name$\";\n\
+        public func IsRock(){return true;}\nfunc Initialize(int x,y){\n    var a=x+y*-2,b=[1,2 ,3];\n\
+        if(a>3) {b=CreateObject(Rock,0,0);b->~Hit();}\n    else return;\n\
+        for(var i in [1,2,3]) a+=i; // sum\n  return _inherited(x,y,...);\n}\n";
+
+    #[test]
+    fn formats_code_style() {
+        assert_eq!(format(MESSY), "/*-- Clonk --*/\n\n#include Library_Foo\nlocal Name = \"$Name$\";\n\
+            public func IsRock() { return true; }\nfunc Initialize(int x, y)\n{\n\tvar a = x + y * -2, b = [1, 2, 3];\n\
+            \tif (a > 3) { b = CreateObject(Rock, 0, 0); b->~Hit(); }\n\telse return;\n\
+            \tfor (var i in [1, 2, 3]) a += i; // sum\n\treturn _inherited(x, y, ...);\n}\n");
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let once = format(MESSY);
+        assert_eq!(format(&once), once);
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let crlf = MESSY.replace('\n', "\r\n");
+        let formatted = format(&crlf);
+        assert_eq!(formatted, format(MESSY).replace('\n', "\r\n"));
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn hangs_bodies_without_braces() {
+        assert_eq!(format("func f() {\nif (a)\nif (b)\nc();\nd();\n}"),
+            "func f()\n{\n\tif (a)\n\t\tif (b)\n\t\t\tc();\n\td();\n}\n");
+    }
+
+    #[test]
+    fn edits_only_touch_changed_lines() {
+        let old = "func f()\n{\n\ta();\n  b();\n\tc();\n}\n";
+        let edits = text_edits(old, &format(old));
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].range.start.line, 3);
+        assert_eq!(edits[0].new_text, "\tb();\n");
+        assert_eq!(apply(old, &edits), format(old));
+    }
+
+    #[test]
+    fn edits_reproduce_the_new_text() {
+        for (old, new) in &[("a\nb\nc", "a\nx\nc\n"), ("", "x\n"), ("x", ""), ("a\nb\n", "b\n"), ("a\r\nb\r\n", "a\r\nc\r\nb\r\n")] {
+            assert_eq!(apply(old, &text_edits(old, new)), *new);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Index of the symbols declared in the whole workspace, for
+//! `workspace/symbol`.
+
+use crate::ast::{Item, VarScope, Visibility};
+use crate::parser;
+use crate::utils::LineIndex;
+use crate::vfs;
+use crate::workspace;
+use lsp_types::{Position, Range, SymbolKind};
+use crossbeam_channel::{Receiver, TryRecvError};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub range: Range,
+}
+
+/// Symbols by file, ordered by path.
+#[derive(Clone, Default)]
+pub struct Index {
+    files: BTreeMap<PathBuf, Vec<Symbol>>,
+}
+
+impl Index {
+    /// Indexes all scripts and the IDs of all definitions.
+    pub fn build(scripts: &[PathBuf], definitions: &HashMap<String, PathBuf>) -> Index {
+        let mut index = Index::default();
+        for path in scripts {
+            if let Ok(content) = vfs::read(path) {
+                index.update(path.clone(), &String::from_utf8_lossy(&content));
+            }
+        }
+        for (id, script) in definitions {
+            let defcore = script.with_file_name("DefCore.txt");
+            let range = defcore_id_range(&defcore).unwrap_or_default();
+            index.files.entry(defcore).or_default().push(Symbol { name: id.clone(), kind: SymbolKind::Class, range });
+        }
+        index
+    }
+
+    /// Replaces the symbols of a script.
+    pub fn update(&mut self, path: PathBuf, code: &str) {
+        self.files.insert(path, script_symbols(code));
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (&PathBuf, &Vec<Symbol>)> {
+        self.files.iter()
+    }
+
+    /// Global functions and constants of all scripts.
+    pub fn globals(&self) -> impl Iterator<Item = (&PathBuf, &Symbol)> {
+        self.files.iter()
+            .flat_map(|(path, symbols)| symbols.iter().map(move |symbol| (path, symbol)))
+            .filter(|(_, s)| s.kind == SymbolKind::Function || s.kind == SymbolKind::Constant)
+    }
+}
+
+/// The workspace index, built on a background thread at startup.
+pub struct WorkspaceIndex {
+    crawler: Option<Receiver<Index>>,
+    /// While the crawler is running, only the scripts saved in the meantime.
+    index: Arc<Index>,
+}
+
+impl WorkspaceIndex {
+    /// Starts crawling the given scripts and definitions.
+    pub fn crawl(scripts: Arc<Vec<PathBuf>>, definitions: Arc<HashMap<String, PathBuf>>) -> WorkspaceIndex {
+        let (sender, crawler) = crossbeam_channel::bounded(1);
+        thread::spawn(move || sender.send(Index::build(&scripts, &definitions)));
+        WorkspaceIndex { crawler: Some(crawler), index: Arc::default() }
+    }
+
+    /// Returns a snapshot of the index. It stays incomplete until the
+    /// crawler is done.
+    pub fn get(&mut self) -> Arc<Index> {
+        self.poll();
+        self.index.clone()
+    }
+
+    /// Replaces the symbols of a script.
+    pub fn update(&mut self, path: PathBuf, code: &str) {
+        self.poll();
+        Arc::make_mut(&mut self.index).update(path, code);
+    }
+
+    /// Takes over the crawled index once it is done, keeping the scripts
+    /// saved since.
+    fn poll(&mut self) {
+        let crawled = match self.crawler.as_ref().map(Receiver::try_recv) {
+            Some(Ok(index)) => Some(index),
+            Some(Err(TryRecvError::Empty)) | None => return,
+            Some(Err(TryRecvError::Disconnected)) => None,
+        };
+        self.crawler = None;
+        if let Some(mut index) = crawled {
+            index.files.extend(self.index.files.iter().map(|(path, symbols)| (path.clone(), symbols.clone())));
+            self.index = Arc::new(index);
+        }
+    }
+}
+
+/// Functions and constants declared in a script.
+pub fn script_symbols(code: &str) -> Vec<Symbol> {
+    let script = parser::parse(code);
+    let index = LineIndex::new(code);
+    let mut symbols = Vec::new();
+    for item in &script.items {
+        match item {
+            Item::Function(f) => {
+                let kind = if f.visibility == Some(Visibility::Global) { SymbolKind::Function } else { SymbolKind::Method };
+                symbols.push(Symbol { name: f.name.name.clone(), kind, range: index.range(f.name.span) });
+            },
+            Item::Vars(v) if v.scope == VarScope::StaticConst => {
+                symbols.extend(v.vars.iter().map(|var| Symbol {
+                    name: var.name.name.clone(),
+                    kind: SymbolKind::Constant,
+                    range: index.range(var.name.span),
+                }));
+            },
+            Item::Vars(_) => (),
+        }
+    }
+    symbols.retain(|s| !s.name.is_empty());
+    symbols
+}
+
+/// Whether the characters of the query appear in the name in order,
+/// ignoring case.
+pub fn matches(query: &str, name: &str) -> bool {
+    let mut name = name.chars().flat_map(char::to_lowercase);
+    query.chars().flat_map(char::to_lowercase).all(|q| name.any(|c| c == q))
+}
+
+/// Range of the `id=` line in a DefCore.txt.
+pub fn defcore_id_range(path: &Path) -> Option<Range> {
+    let content = vfs::read(path).ok()?;
+    let content = String::from_utf8_lossy(&content);
+    let (line, _) = workspace::defcore_id(&content)?;
+    let len = content.lines().nth(line)?.trim_end().encode_utf16().count();
+    Some(Range {
+        start: Position { line: line as u64, character: 0 },
+        end: Position { line: line as u64, character: len as u64 },
+    })
+}
@@ -0,0 +1,169 @@
+//! Tokenizer for C4Script source code.
+//!
+//! All positions are byte offsets into the source string. Conversion to LSP
+//! positions happens in `utils`.
+
+/// Byte range in a script.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Smallest span covering both spans.
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start.min(other.start), end: self.end.max(other.end) }
+    }
+
+    /// Whether the offset lies inside the span. The end is inclusive so that a
+    /// cursor directly behind an identifier still counts as "on" it.
+    pub fn contains(self, offset: usize) -> bool {
+        self.start <= offset && offset <= self.end
+    }
+
+    pub fn text(self, src: &str) -> &str {
+        &src[self.start..self.end]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Ident,
+    Number,
+    String,
+    LineComment,
+    BlockComment,
+    /// A `#include`-style directive, spanning the rest of the line.
+    Directive,
+    /// Operators and other punctuation.
+    Punct,
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn is_comment(&self) -> bool {
+        self.kind == TokenKind::LineComment || self.kind == TokenKind::BlockComment
+    }
+}
+
+/// Regex matching the `/*-- Section --*/` comments that structure scripts,
+/// capturing the title.
+pub const SECTION_HEADER: &str = r"^/\*\s*-+\s*(.*?)\s*-+\s*\*/$";
+
+/// Multi-character operators, longest first.
+const OPERATORS: &[&str] = &[
+    "->~", "<<=", ">>=", "??=", "...",
+    "->", "==", "!=", "<=", ">=", "&&", "||", "++", "--", "+=", "-=", "*=", "/=", "%=",
+    "&=", "|=", "^=", "<<", ">>", "**", "??",
+];
+
+/// Words that can't be used as names of functions or variables.
+const RESERVED_WORDS: &[&str] = &[
+    "if", "else", "while", "for", "do", "return", "break", "continue", "var", "local", "static",
+    "const", "func", "public", "protected", "private", "global", "new", "true", "false", "nil",
+    "this", "inherited", "_inherited",
+];
+
+/// Whether the name is a valid identifier that doesn't clash with a keyword.
+pub fn is_identifier(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.first().map_or(false, |&c| is_ident_start(c))
+        && bytes.iter().all(|&c| is_ident_char(c))
+        && !RESERVED_WORDS.contains(&name)
+}
+
+fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+fn is_ident_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// Splits a script into tokens. Whitespace is dropped, comments are kept.
+pub fn tokenize(src: &str) -> Vec<Token> {
+    let bytes = src.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    // Directives are only recognized at the beginning of a line.
+    let mut line_start = true;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        let kind = if c == b'\n' {
+            line_start = true;
+            i += 1;
+            continue;
+        } else if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        } else if c == b'#' && line_start {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            TokenKind::Directive
+        } else if bytes[i..].starts_with(b"//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            TokenKind::LineComment
+        } else if bytes[i..].starts_with(b"/*") {
+            i += 2;
+            while i < bytes.len() && !bytes[i..].starts_with(b"*/") {
+                i += 1;
+            }
+            i = (i + 2).min(bytes.len());
+            TokenKind::BlockComment
+        } else if c == b'"' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'"' && bytes[i] != b'\n' {
+                if bytes[i] == b'\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i = i.min(bytes.len());
+            if i < bytes.len() && bytes[i] == b'"' {
+                i += 1;
+            }
+            TokenKind::String
+        } else if is_ident_start(c) {
+            while i < bytes.len() && is_ident_char(bytes[i]) {
+                i += 1;
+            }
+            TokenKind::Ident
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && is_ident_char(bytes[i]) {
+                i += 1;
+            }
+            TokenKind::Number
+        } else if let Some(op) = OPERATORS.iter().find(|op| bytes[i..].starts_with(op.as_bytes())) {
+            i += op.len();
+            TokenKind::Punct
+        } else if c.is_ascii_punctuation() {
+            i += 1;
+            TokenKind::Punct
+        } else {
+            // Skip over a full UTF-8 character.
+            i += 1;
+            while i < bytes.len() && !src.is_char_boundary(i) {
+                i += 1;
+            }
+            TokenKind::Unknown
+        };
+        line_start = false;
+        tokens.push(Token { kind, span: Span::new(start, i) });
+    }
+    tokens
+}
@@ -23,8 +23,29 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+mod actions;
+mod ast;
+mod c4group;
 mod c4script_sys;
 mod c4script;
+mod diagnostics;
+mod document;
+mod engine;
+mod ext;
+mod folding;
+mod format;
+mod index;
+mod lexer;
+mod parser;
+mod scope;
+mod semantic;
+mod signature;
+mod symbols;
+#[cfg(test)]
+mod test_utils;
+mod utils;
+mod vfs;
+mod workspace;
 
 use log::{error, trace, warn};
 use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
@@ -34,10 +55,21 @@ use lsp_types::{
     request::{*, Request as RequestTrait},
 };
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
     panic,
+    path::PathBuf,
     process,
+    rc::Rc,
+    time::Duration,
 };
+use diagnostics::Checker;
+use document::Document;
+use index::WorkspaceIndex;
+use lexer::Span;
+use scope::{DeclKind, RefKind};
+use utils::LineIndex;
+use workspace::Workspace;
 
 type Error = Box<dyn std::error::Error>;
 
@@ -57,30 +89,60 @@ fn real_main() -> Result<(), Error> {
     }));
 
     let (connection, io_threads) = Connection::stdio();
-    let capabilities = serde_json::to_value(&ServerCapabilities {
+    let mut capabilities = serde_json::to_value(&ServerCapabilities {
         text_document_sync: Some(TextDocumentSyncCapability::Options(
             TextDocumentSyncOptions {
                 open_close: Some(true),
-                change: Some(TextDocumentSyncKind::Full),
+                change: Some(TextDocumentSyncKind::Incremental),
+                save: Some(SaveOptions::default()),
                 ..TextDocumentSyncOptions::default()
             }
         )),
         completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".into(), ">".into()]),
             ..CompletionOptions::default()
         }),
         definition_provider: Some(true),
+        references_provider: Some(true),
+        document_symbol_provider: Some(true),
+        hover_provider: Some(true),
         document_formatting_provider: Some(true),
         rename_provider: Some(RenameProviderCapability::Simple(true)),
+        call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(false) }),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
         selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+        workspace_symbol_provider: Some(true),
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: Some(vec!["(".into(), ",".into()]),
+            ..SignatureHelpOptions::default()
+        }),
         ..ServerCapabilities::default()
     }).unwrap();
+    capabilities["semanticTokensProvider"] = serde_json::json!({
+        "legend": {
+            "tokenTypes": semantic::TOKEN_TYPES,
+            "tokenModifiers": semantic::TOKEN_MODIFIERS,
+        },
+        "full": true,
+        "range": true,
+    });
+
+    let params: InitializeParams = serde_json::from_value(connection.initialize(capabilities)?)?;
+    let roots = match (params.workspace_folders, params.root_uri) {
+        (Some(folders), _) => folders.into_iter().map(|f| f.uri).collect(),
+        (None, Some(uri)) => vec![uri],
+        (None, None) => Vec::new(),
+    };
 
-    connection.initialize(capabilities)?;
+    let pull_configuration = params.capabilities.workspace.as_ref()
+        .and_then(|w| w.configuration)
+        .unwrap_or(false);
 
-    App {
-        files: HashMap::new(),
-        conn: connection,
-    }.main();
+    let mut workspace = Workspace::new(roots.iter().filter_map(vfs::to_path).collect());
+    workspace.set_planet(params.initialization_options.as_ref().and_then(planet_path));
+    App::new(connection, workspace, pull_configuration).main();
 
     io_threads.join()?;
 
@@ -88,18 +150,58 @@ fn real_main() -> Result<(), Error> {
 }
 
 struct App {
-    files: HashMap<Url, String>,
+    files: HashMap<Url, Document>,
+    checker: Checker,
     conn: Connection,
+    workspace: Workspace,
+    /// Symbols of all scripts on disk.
+    index: WorkspaceIndex,
+    /// Whether the client supports `workspace/configuration`.
+    pull_configuration: bool,
+    /// Number of `workspace/configuration` requests sent so far.
+    configuration_requests: u64,
+    /// ID of the latest `workspace/configuration` request until its
+    /// response arrives. Responses to earlier ones are outdated.
+    pending_configuration: Option<RequestId>,
+    /// Parsed scripts, dropped when a script changes.
+    parsed: RefCell<HashMap<Url, Rc<ScriptFile>>>,
+    /// Scripts appending to each definition, built on first use.
+    appendtos: Option<Rc<HashMap<String, Vec<Url>>>>,
+    /// Override order of the scripts of each definition, by the
+    /// definition's script.
+    override_orders: HashMap<Url, Rc<Vec<Url>>>,
+}
+
+/// Prefix of the IDs of our `workspace/configuration` requests.
+const CONFIGURATION_REQUEST: &str = "oclsp/configuration";
+
+/// A parsed script, either from an open buffer or from disk.
+struct ScriptFile {
+    uri: Url,
+    code: String,
+    script: ast::Script,
 }
 impl App {
+    fn new(conn: Connection, mut workspace: Workspace, pull_configuration: bool) -> App {
+        let index = WorkspaceIndex::crawl(workspace.scripts(), workspace.definitions());
+        App {
+            files: HashMap::new(),
+            checker: Checker::new(conn.sender.clone()),
+            conn,
+            workspace,
+            index,
+            pull_configuration,
+            configuration_requests: 0,
+            pending_configuration: None,
+            parsed: RefCell::default(),
+            appendtos: None,
+            override_orders: HashMap::new(),
+        }
+    }
     fn reply(&mut self, response: Response) {
         trace!("Sending response: {:#?}", response);
         self.conn.sender.send(Message::Response(response)).unwrap();
     }
-    fn notify(&mut self, notification: Notification) {
-        trace!("Sending notification: {:#?}", notification);
-        self.conn.sender.send(Message::Notification(notification)).unwrap();
-    }
     fn err<E>(&mut self, id: RequestId, err: E)
         where E: std::fmt::Display
     {
@@ -107,6 +209,9 @@ impl App {
         self.reply(Response::new_err(id, ErrorCode::UnknownErrorCode as i32, err.to_string()));
     }
     fn main(&mut self) {
+        if self.pull_configuration {
+            self.request_configuration();
+        }
         while let Ok(msg) = self.conn.receiver.recv() {
             trace!("Message: {:#?}", msg);
             match msg {
@@ -130,7 +235,7 @@ impl App {
                 Message::Notification(notification) => {
                     let _ = self.handle_notification(notification);
                 },
-                Message::Response(_) => (),
+                Message::Response(response) => self.handle_response(response),
             }
         }
     }
@@ -155,142 +260,1181 @@ impl App {
             } else {
                 self.reply(Response::new_ok(id, ()));
             }
+        } else if let Some((id, params)) = cast::<HoverRequest>(&mut req) {
+            if let Some(hover) = self.hover(params) {
+                self.reply(Response::new_ok(id, hover));
+            } else {
+                self.reply(Response::new_ok(id, ()));
+            }
+        } else if let Some((id, params)) = cast::<SignatureHelpRequest>(&mut req) {
+            if let Some(help) = self.signature_help(params) {
+                self.reply(Response::new_ok(id, help));
+            } else {
+                self.reply(Response::new_ok(id, ()));
+            }
+        } else if let Some((id, params)) = cast::<References>(&mut req) {
+            let locations = self.references(params).unwrap_or_default();
+            self.reply(Response::new_ok(id, locations));
+        } else if let Some((id, params)) = cast::<ext::InheritanceRequest>(&mut req) {
+            let inheritance = self.inheritance(params);
+            self.reply(Response::new_ok(id, inheritance));
+        } else if let Some((id, params)) = cast::<CallHierarchyPrepare>(&mut req) {
+            let items = self.prepare_call_hierarchy(params.text_document_position_params);
+            self.reply(Response::new_ok(id, items));
+        } else if let Some((id, params)) = cast::<CallHierarchyIncomingCalls>(&mut req) {
+            let calls = self.incoming_calls(&params.item);
+            self.reply(Response::new_ok(id, calls));
+        } else if let Some((id, params)) = cast::<CallHierarchyOutgoingCalls>(&mut req) {
+            let calls = self.outgoing_calls(&params.item).unwrap_or_default();
+            self.reply(Response::new_ok(id, calls));
         } else if let Some((id, params)) = cast::<Completion>(&mut req) {
             let completions = self.completions(&params.text_document_position).unwrap_or_default();
             self.reply(Response::new_ok(id, completions));
         } else if let Some((id, params)) = cast::<Rename>(&mut req) {
-            let changes = self.rename(params);
+            let changes = self.rename(params)?;
             self.reply(Response::new_ok(id, WorkspaceEdit {
-                changes,
+                changes: Some(changes),
                 ..WorkspaceEdit::default()
             }));
         } else if let Some((id, params)) = cast::<Formatting>(&mut req) {
-            let changes: Vec<TextEdit> = if let Some(code) = self.files.get(&params.text_document.uri) {
-                unimplemented!()
-                //let fmt = nixpkgs_fmt::reformat_node(&ast.node());
-                //fmt.text_diff().iter()
-                //    .filter(|range| !range.delete.is_empty() || !range.insert.is_empty())
-                //    .map(|edit| TextEdit {
-                //        range: utils::range(&code, edit.delete),
-                //        new_text: edit.insert.to_string()
-                //    })
-                //    .collect()
+            let changes: Vec<TextEdit> = if let Some(doc) = self.files.get(&params.text_document.uri) {
+                let code = doc.text();
+                format::text_edits(&code, &format::format(&code))
             } else {
                 Vec::new()
             };
             self.reply(Response::new_ok(id, changes));
+        } else if let Some((id, params)) = cast::<DocumentSymbolRequest>(&mut req) {
+            let symbols = match self.load_script(&params.text_document.uri) {
+                Some(file) => symbols::document_symbols(&file.script, &file.code),
+                None => Vec::new(),
+            };
+            self.reply(Response::new_ok(id, DocumentSymbolResponse::Nested(symbols)));
+        } else if let Some((id, params)) = cast::<CodeActionRequest>(&mut req) {
+            let actions = self.code_actions(params).unwrap_or_default();
+            self.reply(Response::new_ok(id, actions));
+        } else if let Some((id, params)) = cast::<CodeLensRequest>(&mut req) {
+            let lenses = self.code_lenses(&params.text_document.uri).unwrap_or_default();
+            self.reply(Response::new_ok(id, lenses));
+        } else if let Some((id, params)) = cast::<FoldingRangeRequest>(&mut req) {
+            let ranges = match self.load_script(&params.text_document.uri) {
+                Some(file) => folding::folding_ranges(&file.script, &file.code),
+                None => Vec::new(),
+            };
+            self.reply(Response::new_ok(id, ranges));
+        } else if let Some((id, params)) = cast::<WorkspaceSymbol>(&mut req) {
+            let symbols = self.workspace_symbols(&params.query);
+            self.reply(Response::new_ok(id, symbols));
+        } else if let Some((id, params)) = cast::<ext::GroupContent>(&mut req) {
+            let content = self.read_file(&params.uri);
+            self.reply(Response::new_ok(id, content));
+        } else if let Some((id, params)) = cast::<ext::SemanticTokensRequest>(&mut req) {
+            let tokens = self.semantic_tokens(&params.text_document.uri, None).unwrap_or_default();
+            self.reply(Response::new_ok(id, tokens));
+        } else if let Some((id, params)) = cast::<ext::SemanticTokensRangeRequest>(&mut req) {
+            let tokens = self.semantic_tokens(&params.text_document.uri, Some(params.range)).unwrap_or_default();
+            self.reply(Response::new_ok(id, tokens));
         } else if let Some((id, params)) = cast::<SelectionRangeRequest>(&mut req) {
             let mut selections: Vec<Option<SelectionRange>> = Vec::new();
-            if let Some(code) = self.files.get(&params.text_document.uri) {
+            if let Some(doc) = self.files.get(&params.text_document.uri) {
+                let code = doc.text();
+                let script = parser::parse(&code);
                 for pos in params.positions {
-                    // TODO
-                    //selections.push(utils::selection_ranges(&ast.node(), code, pos));
+                    selections.push(utils::selection_ranges(&script, &code, pos));
                 }
             }
             self.reply(Response::new_ok(id, selections));
         }
         Ok(())
     }
+    fn handle_response(&mut self, response: Response) {
+        if self.pending_configuration.as_ref() != Some(&response.id) {
+            return;
+        }
+        self.pending_configuration = None;
+        // The result has one entry for our single configuration item.
+        let settings = response.result.as_ref()
+            .and_then(|result| result.get(0))
+            .cloned()
+            .unwrap_or_default();
+        self.configure(&settings);
+    }
     fn handle_notification(&mut self, req: Notification) -> Result<(), Error> {
         match &*req.method {
+            DidChangeConfiguration::METHOD => {
+                let params: DidChangeConfigurationParams = serde_json::from_value(req.params)?;
+                // Clients using `workspace/configuration` often don't send
+                // the settings along.
+                if self.pull_configuration {
+                    self.request_configuration();
+                } else {
+                    let settings = params.settings.get("oclsp").unwrap_or(&params.settings);
+                    self.configure(&settings.clone());
+                }
+            },
             DidOpenTextDocument::METHOD => {
                 let params: DidOpenTextDocumentParams = serde_json::from_value(req.params)?;
-                let text = params.text_document.text;
-                self.send_diagnostics(params.text_document.uri.clone(), &text)?;
-                self.files.insert(params.text_document.uri, text);
+                let doc = params.text_document;
+                if let Some(path) = vfs::to_path(&doc.uri) {
+                    if self.workspace.add_file(&path) {
+                        self.workspace_changed();
+                    }
+                }
+                self.files.insert(doc.uri.clone(), Document::new(&doc.text, Some(doc.version)));
+                self.script_changed(&doc.uri);
+                self.schedule_check(doc.uri, Duration::from_millis(0));
             },
             DidChangeTextDocument::METHOD => {
                 let params: DidChangeTextDocumentParams = serde_json::from_value(req.params)?;
-                if let Some(change) = params.content_changes.into_iter().last() {
-                    self.send_diagnostics(params.text_document.uri.clone(), &change.text)?;
-                    self.files.insert(params.text_document.uri, change.text);
+                let uri = params.text_document.uri;
+                let version = params.text_document.version;
+                if let Some(doc) = self.files.get_mut(&uri) {
+                    for change in &params.content_changes {
+                        doc.apply(change);
+                    }
+                    doc.version = version;
+                    self.script_changed(&uri);
+                    self.schedule_check(uri, diagnostics::DEBOUNCE);
+                }
+            },
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(req.params)?;
+                let uri = params.text_document.uri;
+                // Other scripts continue to see the file as it is on disk.
+                self.files.remove(&uri);
+                self.script_changed(&uri);
+                let dependents = self.dependents(&uri);
+                self.checker.close(uri);
+                for uri in dependents {
+                    self.schedule_check(uri, Duration::from_millis(0));
+                }
+            },
+            DidSaveTextDocument::METHOD => {
+                let params: DidSaveTextDocumentParams = serde_json::from_value(req.params)?;
+                if let Some(path) = vfs::to_path(&params.text_document.uri) {
+                    // The saved file may be a new definition.
+                    if self.workspace.file_saved(&path) {
+                        self.workspace_changed();
+                    }
+                    self.script_changed(&params.text_document.uri);
+                    if let Some(code) = self.read_file(&params.text_document.uri) {
+                        self.index.update(path, &code);
+                    }
+                }
+                for uri in self.dependents(&params.text_document.uri) {
+                    self.schedule_check(uri, Duration::from_millis(0));
                 }
             },
             _ => (),
         }
         Ok(())
     }
+    fn request_configuration(&mut self) {
+        let params = ConfigurationParams {
+            items: vec![ConfigurationItem { scope_uri: None, section: Some("oclsp".to_string()) }],
+        };
+        self.configuration_requests += 1;
+        let id = RequestId::from(format!("{}/{}", CONFIGURATION_REQUEST, self.configuration_requests));
+        self.pending_configuration = Some(id.clone());
+        let request = Request::new(id, WorkspaceConfiguration::METHOD.to_string(), params);
+        self.conn.sender.send(Message::Request(request)).unwrap();
+    }
+    /// Applies changed settings, indexing and checking everything again if
+    /// the planet directory changed. Settings without `planetPath`, e.g. an
+    /// empty response, keep the one from `initializationOptions`.
+    fn configure(&mut self, settings: &serde_json::Value) {
+        if settings.get("planetPath").is_none() || !self.workspace.set_planet(planet_path(settings)) {
+            return;
+        }
+        self.workspace_changed();
+        let mut open: Vec<Url> = self.files.keys().cloned().collect();
+        open.sort();
+        for uri in open {
+            self.schedule_check(uri, Duration::from_millis(0));
+        }
+    }
     fn lookup_definition(&mut self, params: TextDocumentPositionParams) -> Option<Location> {
-        let current_content = self.files.get(&params.text_document.uri)?;
-        // TODO
-        unimplemented!();
-        //let offset = utils::lookup_pos(current_content, params.position)?;
-        //let node = current_ast.node();
-        //let (name, scope) = self.scope_for_ident(params.text_document.uri, &node, offset)?;
-
-        //let var = scope.get(name.as_str())?;
-        //let (_definition_ast, definition_content) = self.files.get(&var.file)?;
-        //Some(Location {
-        //    uri: (*var.file).clone(),
-        //    range: utils::range(definition_content, var.key.text_range())
-        //})
-    }
-    #[allow(clippy::shadow_unrelated)] // false positive
+        let file = self.load_script(&params.text_document.uri)?;
+        let index = LineIndex::new(&file.code);
+        let offset = index.offset(params.position)?;
+        let occurrence = scope::occurrence_at(&file.script, offset)?;
+        let name = &occurrence.ident.name;
+        match occurrence.kind {
+            RefKind::Declaration(_) => Some(Location {
+                uri: file.uri.clone(),
+                range: index.range(occurrence.ident.span),
+            }),
+            RefKind::Include => {
+                let path = self.workspace.definition_script(name)?;
+                Some(Location { uri: vfs::to_uri(&path)?, range: Range::default() })
+            },
+            RefKind::Variable => {
+                if let Some(decl) = scope::resolve_local(&file.script, offset, name) {
+                    return Some(Location { uri: file.uri.clone(), range: index.range(decl.span) });
+                }
+                self.find_declaration(&file, name, &[DeclKind::Local, DeclKind::Static, DeclKind::StaticConst])
+                    .or_else(|| {
+                        // Definition IDs like `Rock` lead to the definition's script.
+                        let path = self.workspace.definition_script(name)?;
+                        Some(Location { uri: vfs::to_uri(&path)?, range: Range::default() })
+                    })
+            },
+            RefKind::Call if name == "inherited" || name == "_inherited" => {
+                let f = scope::enclosing_function(&file.script, offset)?;
+                self.inherited_function(&file.uri, &f.name.name)
+            },
+            RefKind::Call | RefKind::MethodCall => self.find_declaration(&file, name, &[DeclKind::Function]),
+            RefKind::Member | RefKind::PropertyKey => self.find_declaration(&file, name, &[DeclKind::Local]),
+        }
+    }
+    fn hover(&mut self, params: TextDocumentPositionParams) -> Option<Hover> {
+        let file = self.load_script(&params.text_document.uri)?;
+        let index = LineIndex::new(&file.code);
+        let offset = index.offset(params.position)?;
+        let occurrence = scope::occurrence_at(&file.script, offset)?;
+        let function = match occurrence.kind {
+            RefKind::Call | RefKind::MethodCall => engine::function(&occurrence.ident.name)?,
+            _ => return None,
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: function.documentation(),
+            }),
+            range: Some(index.range(occurrence.ident.span)),
+        })
+    }
+    /// Returns the contents of an open document or reads it from disk.
+    fn read_file(&self, uri: &Url) -> Option<String> {
+        if let Some(doc) = self.files.get(uri) {
+            return Some(doc.text());
+        }
+        let content = vfs::read(&vfs::to_path(uri)?).ok()?;
+        // Older scripts are often not UTF-8.
+        Some(String::from_utf8_lossy(&content).into_owned())
+    }
+    fn load_script(&self, uri: &Url) -> Option<Rc<ScriptFile>> {
+        if let Some(file) = self.parsed.borrow().get(uri) {
+            return Some(file.clone());
+        }
+        let code = self.read_file(uri)?;
+        let script = parser::parse(&code);
+        let file = Rc::new(ScriptFile { uri: uri.clone(), code, script });
+        self.parsed.borrow_mut().insert(uri.clone(), file.clone());
+        Some(file)
+    }
+    /// Forgets everything derived from a script after it changed.
+    fn script_changed(&mut self, uri: &Url) {
+        self.parsed.get_mut().remove(uri);
+        self.forget_inheritance();
+    }
+    /// Indexes the workspace again after its roots or definitions changed.
+    fn workspace_changed(&mut self) {
+        self.index = WorkspaceIndex::crawl(self.workspace.scripts(), self.workspace.definitions());
+        self.forget_inheritance();
+    }
+    /// Forgets how scripts are combined, e.g. after the set of scripts changed.
+    fn forget_inheritance(&mut self) {
+        self.appendtos = None;
+        self.override_orders.clear();
+    }
+    /// Returns the script followed by all scripts it pulls in via `#include`
+    /// and `#appendto`, transitively.
+    fn include_chain(&mut self, uri: &Url) -> Vec<Rc<ScriptFile>> {
+        let mut result: Vec<Rc<ScriptFile>> = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = vec![uri.clone()];
+        seen.insert(uri.clone());
+        while !queue.is_empty() {
+            let uri = queue.remove(0);
+            let file = match self.load_script(&uri) {
+                Some(file) => file,
+                None => continue,
+            };
+            for id in file.script.includes() {
+                let included = self.workspace.definition_script(&id.name)
+                    .and_then(|path| vfs::to_uri(&path));
+                if let Some(included) = included {
+                    if seen.insert(included.clone()) {
+                        queue.push(included);
+                    }
+                }
+            }
+            result.push(file);
+        }
+        result
+    }
+    /// Maps definition IDs to the scripts appending to them.
+    fn appendtos(&mut self) -> Rc<HashMap<String, Vec<Url>>> {
+        if self.appendtos.is_none() {
+            let files: Vec<Rc<ScriptFile>> = self.workspace_scripts().iter().filter_map(|uri| self.load_script(uri)).collect();
+            self.appendtos = Some(Rc::new(appendto_map(&files)));
+        }
+        self.appendtos.clone().unwrap()
+    }
+    fn definition_id(&mut self, uri: &Url) -> Option<String> {
+        let path = vfs::to_path(uri)?;
+        self.workspace.definitions().iter().find(|(_, script)| **script == path).map(|(id, _)| id.clone())
+    }
+    /// The definition script a script belongs to, which is the script itself
+    /// unless it is appended to another definition.
+    fn definition_root(&mut self, file: &ScriptFile) -> Url {
+        let target = file.script.directive_arguments(&ast::DirectiveKind::Appendto).next()
+            .and_then(|id| self.workspace.definition_script(&id.name))
+            .and_then(|path| vfs::to_uri(&path));
+        target.unwrap_or_else(|| file.uri.clone())
+    }
+    /// All scripts making up the definition a script belongs to, in the
+    /// order functions are looked up: scripts appended to it, last one
+    /// first, the definition's script and then everything included,
+    /// recursively and again the last include first.
+    fn override_order(&mut self, uri: &Url) -> Vec<Rc<ScriptFile>> {
+        fn visit(app: &mut App, uri: &Url, appendtos: &HashMap<String, Vec<Url>>, seen: &mut HashSet<Url>, result: &mut Vec<Rc<ScriptFile>>) {
+            if !seen.insert(uri.clone()) {
+                return;
+            }
+            let mut parts: Vec<Rc<ScriptFile>> = app.load_script(uri).into_iter().collect();
+            if let Some(appended) = app.definition_id(uri).and_then(|id| appendtos.get(&id)) {
+                for uri in appended {
+                    if seen.insert(uri.clone()) {
+                        parts.extend(app.load_script(uri));
+                    }
+                }
+            }
+            let includes: Vec<String> = parts.iter()
+                .flat_map(|part| part.script.directive_arguments(&ast::DirectiveKind::Include).map(|id| id.name.clone()))
+                .collect();
+            result.extend(parts.into_iter().rev());
+            for id in includes.iter().rev() {
+                if let Some(included) = app.workspace.definition_script(id).and_then(|path| vfs::to_uri(&path)) {
+                    visit(app, &included, appendtos, seen, result);
+                }
+            }
+        }
+        let file = match self.load_script(uri) {
+            Some(file) => file,
+            None => return Vec::new(),
+        };
+        let root = self.definition_root(&file);
+        let order = match self.override_orders.get(&root) {
+            Some(order) => order.clone(),
+            None => {
+                let appendtos = self.appendtos();
+                let mut result = Vec::new();
+                visit(self, &root, &appendtos, &mut HashSet::new(), &mut result);
+                let order = Rc::new(result.iter().map(|f| f.uri.clone()).collect::<Vec<Url>>());
+                self.override_orders.insert(root, order.clone());
+                order
+            },
+        };
+        order.iter().filter_map(|uri| self.load_script(uri)).collect()
+    }
+    /// Finds the script declaring the local or static a variable in the
+    /// script refers to. The scripts making up the script's definition come
+    /// first, then the other `files`.
+    fn variable_declaration(&mut self, file: &ScriptFile, files: &[Rc<ScriptFile>], name: &str) -> Option<(Rc<ScriptFile>, DeclKind)> {
+        let own = self.override_order(&file.uri);
+        own.iter().chain(files).find_map(|f| {
+            scope::script_declarations(&f.script).into_iter()
+                .find(|decl| decl.name == name && decl.kind != DeclKind::Function)
+                .map(|decl| (f.clone(), decl.kind))
+        })
+    }
+    /// Determines what kind of script-level declaration a reference in the
+    /// script points to. Variables may name a local or a static, so the
+    /// scripts are searched.
+    fn declaration_kind(&mut self, file: &ScriptFile, files: &[Rc<ScriptFile>], name: &str, kind: RefKind) -> Option<DeclKind> {
+        match kind {
+            RefKind::Declaration(kind) => Some(kind),
+            RefKind::Call | RefKind::MethodCall => Some(DeclKind::Function),
+            RefKind::Member | RefKind::PropertyKey => Some(DeclKind::Local),
+            RefKind::Variable => self.variable_declaration(file, files, name).map(|(_, kind)| kind),
+            RefKind::Include => None,
+        }
+    }
+    /// Finds the function `inherited` calls in the named function of the
+    /// script: the next one in the override order.
+    fn inherited_function(&mut self, uri: &Url, name: &str) -> Option<Location> {
+        let order = self.override_order(uri);
+        let position = order.iter().position(|f| &f.uri == uri)?;
+        order[position + 1..].iter().find_map(|file| {
+            scope::script_declarations(&file.script).into_iter()
+                .find(|decl| decl.name == name && decl.kind == DeclKind::Function)
+                .map(|decl| Location { uri: file.uri.clone(), range: utils::range(&file.code, decl.span) })
+        })
+    }
+    fn code_actions(&mut self, params: CodeActionParams) -> Option<Vec<CodeActionOrCommand>> {
+        let uri = params.text_document.uri;
+        let file = self.load_script(&uri)?;
+        let own_id = self.definition_id(&uri);
+        let mut actions = Vec::new();
+        for diagnostic in &params.context.diagnostics {
+            let mut includes = |name: &str| self.declaring_definitions(name).into_iter()
+                .filter(|id| Some(id) != own_id.as_ref())
+                .collect();
+            actions.extend(actions::quick_fixes(&uri, &file.script, &file.code, diagnostic, &mut includes).into_iter()
+                .map(CodeActionOrCommand::CodeAction));
+        }
+        Some(actions)
+    }
+    /// IDs of the definitions whose scripts declare a function.
+    fn declaring_definitions(&mut self, name: &str) -> Vec<String> {
+        let paths: Vec<PathBuf> = self.index.get().files()
+            .filter(|(_, symbols)| symbols.iter().any(|s| s.name == name && s.kind == SymbolKind::Method))
+            .map(|(path, _)| path.clone())
+            .collect();
+        let mut ids: Vec<String> = self.workspace.definitions().iter()
+            .filter(|(_, script)| paths.contains(script))
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+    /// Shows above each function which function it overrides and where it
+    /// is overridden itself. The lenses use the `editor.action.showReferences`
+    /// command with the document URI, the position and the locations.
+    fn code_lenses(&mut self, uri: &Url) -> Option<Vec<CodeLens>> {
+        let file = self.load_script(uri)?;
+        let index = LineIndex::new(&file.code);
+        let files: Vec<Rc<ScriptFile>> = self.project_scripts().iter().filter_map(|uri| self.load_script(uri)).collect();
+        let mut lenses = Vec::new();
+        for item in &file.script.items {
+            let f = match item {
+                ast::Item::Function(f) if !f.name.name.is_empty() => f,
+                _ => continue,
+            };
+            let name = &f.name.name;
+            let range = index.range(f.name.span);
+            if let Some(location) = self.inherited_function(uri, name) {
+                let title = format!("overrides {}.{}", self.inheritance_item(&location.uri).name, name);
+                lenses.push(location_lens(uri, range, title, vec![location]));
+            }
+            // Scripts whose `inherited` leads to this function.
+            let mut overriding = Vec::new();
+            for other in files.iter().filter(|other| &other.uri != uri) {
+                let declared = scope::script_declarations(&other.script).into_iter()
+                    .find(|decl| &decl.name == name && decl.kind == DeclKind::Function);
+                if let Some(decl) = declared {
+                    let overridden = self.inherited_function(&other.uri, name)
+                        .map_or(false, |location| &location.uri == uri);
+                    if overridden {
+                        overriding.push(Location { uri: other.uri.clone(), range: utils::range(&other.code, decl.span) });
+                    }
+                }
+            }
+            if !overriding.is_empty() {
+                let names: Vec<String> = overriding.iter().map(|l| self.inheritance_item(&l.uri).name).collect();
+                let title = format!("overridden in {}", names.join(", "));
+                lenses.push(location_lens(uri, range, title, overriding));
+            }
+        }
+        Some(lenses)
+    }
+    fn inheritance(&mut self, params: TextDocumentPositionParams) -> Option<ext::Inheritance> {
+        let uri = params.text_document.uri;
+        let file = self.load_script(&uri)?;
+        let root = self.definition_root(&file);
+        let id = self.definition_id(&root);
+        let all_appendtos = self.appendtos();
+        let appended: Vec<Url> = id.as_ref()
+            .and_then(|id| all_appendtos.get(id))
+            .cloned()
+            .unwrap_or_default();
+        let mut includes = Vec::new();
+        let parts: Vec<Rc<ScriptFile>> = std::iter::once(&root).chain(&appended).filter_map(|uri| self.load_script(uri)).collect();
+        for part in parts {
+            for included in part.script.directive_arguments(&ast::DirectiveKind::Include) {
+                if let Some(script) = self.workspace.definition_script(&included.name).and_then(|path| vfs::to_uri(&path)) {
+                    includes.push(ext::InheritanceItem { name: included.name.clone(), uri: script });
+                }
+            }
+        }
+        let appendtos = appended.iter().map(|uri| self.inheritance_item(uri)).collect();
+        let override_order = self.override_order(&uri).iter().map(|f| self.inheritance_item(&f.uri)).collect();
+        let inherited = LineIndex::new(&file.code).offset(params.position)
+            .and_then(|offset| scope::enclosing_function(&file.script, offset))
+            .filter(|f| !f.name.name.is_empty())
+            .and_then(|f| self.inherited_function(&uri, &f.name.name));
+        Some(ext::Inheritance { id, includes, appendtos, override_order, inherited })
+    }
+    fn inheritance_item(&mut self, uri: &Url) -> ext::InheritanceItem {
+        // Other scripts are named after their group and file, e.g. `System.ocg/Patch.c`.
+        let file_name = || {
+            let path = vfs::to_path(uri)?;
+            Some(format!("{}/{}", script_name(uri)?, path.file_name()?.to_string_lossy()))
+        };
+        let name = self.definition_id(uri)
+            .or_else(file_name)
+            .unwrap_or_else(|| uri.to_string());
+        ext::InheritanceItem { name, uri: uri.clone() }
+    }
+    /// Returns the given script and all open scripts including it, directly
+    /// or transitively.
+    fn dependents(&mut self, uri: &Url) -> Vec<Url> {
+        let mut open: Vec<Url> = self.files.keys().cloned().collect();
+        open.sort();
+        open.into_iter()
+            .filter(|other| other == uri || self.include_chain(other).iter().any(|f| &f.uri == uri))
+            .collect()
+    }
+    /// Scripts to search for names used in a script: its include chain,
+    /// followed by all other open scripts.
+    fn lookup_scripts(&mut self, file: &ScriptFile) -> Vec<Rc<ScriptFile>> {
+        let mut candidates = self.include_chain(&file.uri);
+        let mut others: Vec<&Url> = self.files.keys()
+            .filter(|uri| !candidates.iter().any(|c| &c.uri == *uri))
+            .collect();
+        others.sort();
+        let others: Vec<Rc<ScriptFile>> = others.into_iter().filter_map(|uri| self.load_script(uri)).collect();
+        candidates.extend(others);
+        candidates
+    }
+    /// Searches a declaration in the script and everything it includes. As
+    /// the target of calls and global names cannot always be determined
+    /// statically, all other open scripts are searched afterwards.
+    fn find_declaration(&mut self, file: &ScriptFile, name: &str, kinds: &[DeclKind]) -> Option<Location> {
+        let found = self.lookup_scripts(file).iter().find_map(|candidate| {
+            scope::script_declarations(&candidate.script).into_iter()
+                .find(|decl| decl.name == name && kinds.contains(&decl.kind))
+                .map(|decl| Location {
+                    uri: candidate.uri.clone(),
+                    range: utils::range(&candidate.code, decl.span),
+                })
+        });
+        // Global functions and constants may be declared anywhere, e.g. in
+        // System.ocg.
+        found.or_else(|| {
+            self.index.get().globals()
+                .find(|(_, symbol)| symbol.name == name && kinds.contains(&decl_kind(symbol.kind)))
+                .and_then(|(path, symbol)| Some(Location { uri: vfs::to_uri(path)?, range: symbol.range }))
+        })
+    }
+    fn semantic_tokens(&mut self, uri: &Url, range: Option<Range>) -> Option<ext::SemanticTokens> {
+        let file = self.load_script(uri)?;
+        let span = match range {
+            Some(range) => {
+                let index = LineIndex::new(&file.code);
+                Some(Span::new(index.offset(range.start)?, index.offset(range.end)?))
+            },
+            None => None,
+        };
+        let chain = self.include_chain(uri);
+        let others: Vec<Rc<ScriptFile>> = self.files.keys()
+            .filter(|uri| !chain.iter().any(|c| &c.uri == *uri))
+            .filter_map(|uri| self.load_script(uri))
+            .collect();
+        let mut declarations: Vec<scope::Declaration> = chain.iter()
+            .flat_map(|f| scope::script_declarations(&f.script))
+            .chain(others.iter().flat_map(|f| scope::global_declarations(&f.script)))
+            .collect();
+        declarations.extend(self.index.get().globals().map(|(_, symbol)| scope::Declaration {
+            name: symbol.name.clone(),
+            kind: decl_kind(symbol.kind),
+            span: Span::default(),
+        }));
+        let definitions = self.workspace.definitions();
+        let names = semantic::Names {
+            declarations,
+            is_definition: &|name| definitions.contains_key(name),
+        };
+        Some(ext::SemanticTokens { data: semantic::semantic_tokens(&file.script, &file.code, &names, span) })
+    }
+    fn signature_help(&mut self, params: TextDocumentPositionParams) -> Option<SignatureHelp> {
+        let file = self.load_script(&params.text_document.uri)?;
+        let offset = LineIndex::new(&file.code).offset(params.position)?;
+        let call = signature::call_context(&file.code, offset)?;
+        let info = if call.name == "inherited" || call.name == "_inherited" {
+            // Calls the overridden version of the current function.
+            signature::script_signature(scope::enclosing_function(&file.script, offset)?)
+        } else {
+            self.lookup_scripts(&file).iter()
+                .find_map(|candidate| candidate.script.items.iter().find_map(|item| match item {
+                    ast::Item::Function(f) if f.name.name == call.name => Some(signature::script_signature(f)),
+                    _ => None,
+                }))
+                .or_else(|| engine::function(&call.name).map(signature::engine_signature))?
+        };
+        let params = info.parameters.as_ref().map_or(0, Vec::len);
+        // Everything after the last parameter goes into `...`.
+        let varargs = params > 0 && info.label.ends_with("...)");
+        let active = if varargs { call.active_parameter.min(params - 1) } else { call.active_parameter };
+        Some(SignatureHelp {
+            signatures: vec![info],
+            active_signature: Some(0),
+            active_parameter: Some(active as i64),
+        })
+    }
     fn completions(&mut self, params: &TextDocumentPositionParams) -> Option<Vec<CompletionItem>> {
-        let content = self.files.get(&params.text_document.uri)?;
-        // TODO
-        None
-        //let offset = utils::lookup_pos(content, params.position)?;
-
-        //let node = ast.node();
-        //let (name, scope) = self.scope_for_ident(params.text_document.uri.clone(), &node, offset)?;
-
-        //// Re-open, because scope_for_ident may mutably borrow
-        //let (_, content) = self.files.get(&params.text_document.uri)?;
-
-        //let mut completions = Vec::new();
-        //for var in scope.keys() {
-        //    if var.starts_with(&name.as_str()) {
-        //        completions.push(CompletionItem {
-        //            label: var.clone(),
-        //            text_edit: Some(TextEdit {
-        //                range: utils::range(content, name.node().text_range()),
-        //                new_text: var.clone()
-        //            }),
-        //            ..CompletionItem::default()
-        //        });
-        //    }
-        //}
-        //Some(completions)
-    }
-    fn rename(&mut self, params: RenameParams) -> Option<HashMap<Url, Vec<TextEdit>>> {
-        struct Rename<'a> {
-            edits: Vec<TextEdit>,
-            code: &'a str,
-            old: &'a str,
-            new_name: String,
+        let file = self.load_script(&params.text_document.uri)?;
+        let index = LineIndex::new(&file.code);
+        let offset = index.offset(params.position)?;
+        // Identifiers are ASCII, so this never splits a character.
+        let start = offset - file.code[..offset].bytes().rev()
+            .take_while(|b| b.is_ascii_alphanumeric() || *b == b'_')
+            .count();
+        let prefix = file.code[start..offset].to_ascii_lowercase();
+        let range = index.range(Span::new(start, offset));
+
+        // After `->`, only functions make sense; after `.`, only properties.
+        let before = file.code[..start].trim_end();
+        let kinds: &[DeclKind] = if before.ends_with("->") || before.ends_with("->~") {
+            &[DeclKind::Function]
+        } else if before.ends_with('.') {
+            &[DeclKind::Local]
+        } else {
+            &[DeclKind::Function, DeclKind::Param, DeclKind::Var, DeclKind::Local, DeclKind::Static, DeclKind::StaticConst]
+        };
+
+        let mut candidates: Vec<(String, CompletionItemKind, Option<String>)> = Vec::new();
+        if let Some(f) = scope::enclosing_function(&file.script, offset) {
+            candidates.extend(scope::function_locals(f).into_iter()
+                .map(|decl| (decl.name, completion_kind(decl.kind), None)));
+        }
+        let chain = self.include_chain(&file.uri);
+        let others: Vec<Rc<ScriptFile>> = self.files.keys()
+            .filter(|uri| !chain.iter().any(|c| &c.uri == *uri))
+            .filter_map(|uri| self.load_script(uri))
+            .collect();
+        for (script_file, decls) in chain.iter().map(|f| (f, scope::script_declarations(&f.script)))
+            .chain(others.iter().map(|f| (f, scope::global_declarations(&f.script))))
+        {
+            let detail = script_name(&script_file.uri);
+            candidates.extend(decls.into_iter()
+                .filter(|decl| kinds.contains(&decl.kind))
+                .map(|decl| (decl.name, completion_kind(decl.kind), detail.clone())));
+        }
+        candidates.extend(self.index.get().globals()
+            .filter(|(_, symbol)| kinds.contains(&decl_kind(symbol.kind)))
+            .map(|(path, symbol)| {
+                let detail = vfs::to_uri(path).as_ref().and_then(script_name);
+                (symbol.name.clone(), completion_kind(decl_kind(symbol.kind)), detail)
+            }));
+        if kinds.contains(&DeclKind::Function) {
+            candidates.extend(engine::ENGINE_FUNCTIONS.iter()
+                .map(|f| (f.name.to_string(), CompletionItemKind::Function, Some(f.signature()))));
         }
 
-        let uri = params.text_document_position.text_document.uri;
-        let code = self.files.get(&uri)?;
-        // TODO
-        None
-    }
-    fn send_diagnostics(&mut self, uri: Url, code: &str) -> Result<(), Error> {
-        let mut diagnostics = Vec::new();
-        c4script::check_string(code, |severity, msg, pos| {
-            diagnostics.push(Diagnostic {
-                range: if let Some(p) = pos {
-                    p.to_range()
-                } else {
-                    Range::default()
+        let mut seen = HashSet::new();
+        Some(candidates.into_iter()
+            .filter(|(name, _, _)| name.to_ascii_lowercase().starts_with(&prefix) && seen.insert(name.clone()))
+            .map(|(name, kind, detail)| CompletionItem {
+                label: name.clone(),
+                kind: Some(kind),
+                detail,
+                text_edit: Some(TextEdit { range, new_text: name }),
+                ..CompletionItem::default()
+            })
+            .collect())
+    }
+    /// Finds all references to a function, variable or definition ID. Calls
+    /// that are only resolved at runtime, like `Call("Foo")`, are less
+    /// certain and listed after all other references.
+    fn references(&mut self, params: ReferenceParams) -> Option<Vec<ext::ReferenceLocation>> {
+        let position = params.text_document_position;
+        let include_declaration = params.context.include_declaration;
+        let file = self.load_script(&position.text_document.uri)?;
+        let index = LineIndex::new(&file.code);
+        let offset = index.offset(position.position)?;
+        let occurrence = scope::occurrence_at(&file.script, offset)?;
+        let name = occurrence.ident.name.clone();
+        let certain = |location| ext::ReferenceLocation { location, dynamic: false };
+
+        if let Some(locals) = scope::local_occurrences(&file.script, offset) {
+            return Some(locals.into_iter()
+                .filter(|o| include_declaration || !matches!(o.kind, RefKind::Declaration(_)))
+                .map(|o| Location { uri: file.uri.clone(), range: index.range(o.ident.span) })
+                .map(certain)
+                .collect());
+        }
+
+        let files: Vec<Rc<ScriptFile>> = self.project_scripts().iter().filter_map(|uri| self.load_script(uri)).collect();
+        let kind = self.declaration_kind(&file, &files, &name, occurrence.kind);
+        let mut locations = Vec::new();
+        let mut dynamic = Vec::new();
+        if kind.is_none() {
+            // Definition IDs are used like constants.
+            let defcore = self.workspace.definition_script(&name)?.with_file_name("DefCore.txt");
+            if include_declaration {
+                locations.push(Location {
+                    uri: vfs::to_uri(&defcore)?,
+                    range: index::defcore_id_range(&defcore).unwrap_or_default(),
+                });
+            }
+            for file in &files {
+                let index = LineIndex::new(&file.code);
+                locations.extend(scope::occurrences(&file.script).into_iter()
+                    .filter(|o| o.ident.name == name)
+                    .filter(|o| o.kind == RefKind::Include
+                        || o.kind == RefKind::Variable && scope::resolve_local(&file.script, o.ident.span.start, &name).is_none())
+                    .map(|o| Location { uri: file.uri.clone(), range: index.range(o.ident.span) }));
+            }
+            return Some(locations.into_iter().map(certain).collect());
+        }
+
+        let kind = kind?;
+        for file in &files {
+            let index = LineIndex::new(&file.code);
+            let runtime = if kind == DeclKind::Function {
+                scope::dynamic_references(&file.script, &file.code, &name)
+            } else {
+                Vec::new()
+            };
+            locations.extend(scope::occurrences(&file.script).into_iter()
+                .filter(|o| o.ident.name == name && scope::refers_to(&file.script, o, kind))
+                .filter(|o| include_declaration || !matches!(o.kind, RefKind::Declaration(_)))
+                .filter(|o| !runtime.contains(&o.ident.span))
+                .map(|o| Location { uri: file.uri.clone(), range: index.range(o.ident.span) }));
+            dynamic.extend(runtime.into_iter().map(|span| Location { uri: file.uri.clone(), range: index.range(span) }));
+        }
+        Some(locations.into_iter().map(certain)
+            .chain(dynamic.into_iter().map(|location| ext::ReferenceLocation { location, dynamic: true }))
+            .collect())
+    }
+    fn prepare_call_hierarchy(&mut self, params: TextDocumentPositionParams) -> Option<Vec<CallHierarchyItem>> {
+        let file = self.load_script(&params.text_document.uri)?;
+        let index = LineIndex::new(&file.code);
+        let occurrence = scope::occurrence_at(&file.script, index.offset(params.position)?)?;
+        let location = match occurrence.kind {
+            RefKind::Declaration(DeclKind::Function) => Location {
+                uri: file.uri.clone(),
+                range: index.range(occurrence.ident.span),
+            },
+            RefKind::Call | RefKind::MethodCall => self.find_declaration(&file, &occurrence.ident.name, &[DeclKind::Function])?,
+            _ => return None,
+        };
+        Some(vec![self.function_item(&location)?])
+    }
+    /// Call hierarchy item for the function declared at the location.
+    fn function_item(&mut self, location: &Location) -> Option<CallHierarchyItem> {
+        let file = self.load_script(&location.uri)?;
+        let offset = LineIndex::new(&file.code).offset(location.range.start)?;
+        let f = scope::enclosing_function(&file.script, offset)?;
+        Some(call_item(&file, f))
+    }
+    /// Finds all functions calling the item's function by name, including
+    /// calls that are only resolved at runtime. Engine callbacks get the
+    /// engine as an additional caller.
+    fn incoming_calls(&mut self, item: &CallHierarchyItem) -> Vec<CallHierarchyIncomingCall> {
+        let mut calls = Vec::new();
+        if engine::is_callback(&item.name) {
+            calls.push(CallHierarchyIncomingCall {
+                from: CallHierarchyItem {
+                    name: "Engine".to_string(),
+                    kind: SymbolKind::Event,
+                    tags: None,
+                    detail: Some("engine callback".to_string()),
+                    uri: item.uri.clone(),
+                    range: item.selection_range,
+                    selection_range: item.selection_range,
                 },
-                severity: Some(match severity {
-                    c4script::DiagnosticSeverity::Error   => DiagnosticSeverity::Error,
-                    c4script::DiagnosticSeverity::Warning => DiagnosticSeverity::Warning,
-                }),
-                message: msg,
-                ..Diagnostic::default()
+                from_ranges: vec![item.selection_range],
+            });
+        }
+        let files: Vec<Rc<ScriptFile>> = self.project_scripts().iter().filter_map(|uri| self.load_script(uri)).collect();
+        for file in &files {
+            let index = LineIndex::new(&file.code);
+            calls.extend(scope::callers(&file.script, &file.code, &item.name).into_iter()
+                .map(|(f, spans)| CallHierarchyIncomingCall {
+                    from: call_item(file, f),
+                    from_ranges: spans.into_iter().map(|span| index.range(span)).collect(),
+                }));
+        }
+        calls
+    }
+    /// Lists the script functions the item's function calls directly.
+    fn outgoing_calls(&mut self, item: &CallHierarchyItem) -> Option<Vec<CallHierarchyOutgoingCall>> {
+        let file = self.load_script(&item.uri)?;
+        let index = LineIndex::new(&file.code);
+        let f = scope::enclosing_function(&file.script, index.offset(item.selection_range.start)?)?;
+        Some(scope::callees(&file.script, f).into_iter()
+            .filter_map(|(name, spans)| {
+                let location = self.find_declaration(&file, &name, &[DeclKind::Function])?;
+                let from_ranges = spans.into_iter().map(|span| index.range(span)).collect();
+                Some(CallHierarchyOutgoingCall { to: self.function_item(&location)?, from_ranges })
             })
-        });
-        self.notify(Notification::new(
-            "textDocument/publishDiagnostics".into(),
-            PublishDiagnosticsParams {
-                uri,
-                diagnostics,
-                version: None,
+            .collect())
+    }
+    fn rename(&mut self, params: RenameParams) -> Result<HashMap<Url, Vec<TextEdit>>, Error> {
+        let uri = params.text_document_position.text_document.uri;
+        let new_name = params.new_name;
+        if !lexer::is_identifier(&new_name) {
+            return Err(format!("\"{}\" is not a valid identifier", new_name).into());
+        }
+        if uri.scheme() == vfs::GROUP_SCHEME {
+            return Err("scripts in packed groups are read-only".into());
+        }
+        let file = self.load_script(&uri).ok_or("document is not open")?;
+        let offset = utils::LineIndex::new(&file.code).offset(params.text_document_position.position)
+            .ok_or("invalid position")?;
+        let occurrence = scope::occurrence_at(&file.script, offset).ok_or("no identifier at this position")?;
+        let name = occurrence.ident.name.clone();
+
+        // Parameters and vars only need changes inside their function.
+        if let Some(locals) = scope::local_occurrences(&file.script, offset) {
+            let index = LineIndex::new(&file.code);
+            let edits = locals.into_iter()
+                .map(|o| TextEdit { range: index.range(o.ident.span), new_text: new_name.clone() })
+                .collect();
+            let mut changes = HashMap::new();
+            changes.insert(uri, edits);
+            return Ok(changes);
+        }
+
+        let mut files: Vec<Rc<ScriptFile>> = self.project_scripts().iter().filter_map(|uri| self.load_script(uri)).collect();
+        let kind = match occurrence.kind {
+            RefKind::PropertyKey | RefKind::Include => {
+                return Err(format!("\"{}\" cannot be renamed", name).into());
+            },
+            kind => self.declaration_kind(&file, &files, &name, kind)
+                .ok_or_else(|| format!("\"{}\" is not declared in any script of the workspace", name))?,
+        };
+        if kind == DeclKind::Function {
+            if engine::is_engine_function(&name) {
+                return Err(format!("\"{}\" is an engine function and cannot be renamed", name).into());
             }
-        ));
-        Ok(())
+            if engine::is_callback(&name) {
+                return Err(format!("\"{}\" is called by the engine and cannot be renamed", name).into());
+            }
+            let declared = files.iter()
+                .flat_map(|f| scope::script_declarations(&f.script))
+                .any(|decl| decl.name == name && decl.kind == DeclKind::Function);
+            if !declared {
+                return Err(format!("\"{}\" is not declared in any script of the workspace", name).into());
+            }
+        }
+        // Overriding stock functions only works under their original name.
+        let index = self.index.get();
+        let workspace = &self.workspace;
+        let stock = index.files()
+            .any(|(path, symbols)| workspace.is_planet(path) && symbols.iter().any(|s| s.name == name && s.kind != SymbolKind::Class));
+        if stock {
+            return Err(format!("\"{}\" is declared in the planet directory and cannot be renamed", name).into());
+        }
+        if kind == DeclKind::Local {
+            // `.Name` and `{Name = ...}` may belong to any proplist, so only
+            // plain names can be traced to the local.
+            let property = files.iter().any(|f| scope::occurrences(&f.script).iter()
+                .any(|o| o.ident.name == name && (o.kind == RefKind::Member || o.kind == RefKind::PropertyKey)));
+            if property {
+                return Err(format!("\"{}\" is also used as a property and cannot be renamed safely", name).into());
+            }
+            let declaring = match occurrence.kind {
+                RefKind::Declaration(_) => Some(file.uri.clone()),
+                _ => self.variable_declaration(&file, &files, &name).map(|(f, _)| f.uri.clone()),
+            };
+            let declaring = declaring.ok_or_else(|| format!("\"{}\" is not declared in any script of the workspace", name))?;
+            // Only objects of the declaring definition and those including
+            // it have the local.
+            let mut owners = Vec::new();
+            for f in files {
+                if self.override_order(&f.uri).iter().any(|part| part.uri == declaring) {
+                    owners.push(f);
+                }
+            }
+            files = owners;
+        }
+
+        let mut changes = HashMap::new();
+        for file in files {
+            let index = LineIndex::new(&file.code);
+            let edits: Vec<TextEdit> = scope::occurrences(&file.script).into_iter()
+                .filter(|o| o.ident.name == name && scope::refers_to(&file.script, o, kind))
+                .map(|o| TextEdit { range: index.range(o.ident.span), new_text: new_name.clone() })
+                .collect();
+            if !edits.is_empty() {
+                if file.uri.scheme() == vfs::GROUP_SCHEME {
+                    return Err(format!("\"{}\" is used in a packed group and cannot be renamed", name).into());
+                }
+                changes.insert(file.uri.clone(), edits);
+            }
+        }
+        Ok(changes)
+    }
+    /// Searches functions, constants and definition IDs in the whole
+    /// workspace. Open documents are searched in their current state.
+    fn workspace_symbols(&mut self, query: &str) -> Vec<SymbolInformation> {
+        let open: HashMap<PathBuf, Vec<index::Symbol>> = self.files.iter()
+            .filter_map(|(uri, doc)| Some((vfs::to_path(uri)?, index::script_symbols(&doc.text()))))
+            .collect();
+        let index = self.index.get();
+        let mut symbols: Vec<SymbolInformation> = index.files()
+            .filter(|(path, _)| !open.contains_key(*path))
+            .chain(open.iter())
+            .flat_map(|(path, symbols)| symbols.iter().map(move |symbol| (path, symbol)))
+            .filter(|(_, symbol)| index::matches(query, &symbol.name))
+            .filter_map(|(path, symbol)| {
+                let uri = vfs::to_uri(path)?;
+                Some(SymbolInformation {
+                    name: symbol.name.clone(),
+                    kind: symbol.kind,
+                    deprecated: None,
+                    container_name: script_name(&uri),
+                    location: Location { uri, range: symbol.range },
+                })
+            })
+            .collect();
+        symbols.sort_by(|a, b| (&a.name, &a.location.uri).cmp(&(&b.name, &b.location.uri)));
+        symbols
+    }
+    /// All scripts in the workspace, including open documents outside of it.
+    fn workspace_scripts(&mut self) -> Vec<Url> {
+        let mut uris: Vec<Url> = self.workspace.scripts().iter()
+            .filter_map(|path| vfs::to_uri(path))
+            .chain(self.files.keys().cloned())
+            .collect();
+        uris.sort();
+        uris.dedup();
+        uris
+    }
+    /// Scripts whose references are searched and renamed: all of the
+    /// workspace, without the stock content of the planet directory.
+    fn project_scripts(&mut self) -> Vec<Url> {
+        let mut uris = self.workspace_scripts();
+        let workspace = &self.workspace;
+        uris.retain(|uri| !vfs::to_path(uri).map_or(false, |path| workspace.is_planet(&path)));
+        uris
+    }
+    /// Has the checker look at the current text of an open document. Only
+    /// the document is sent, so the worker neither scans the workspace nor
+    /// reads any other script.
+    fn schedule_check(&mut self, uri: Url, delay: Duration) {
+        let document = match self.files.get(&uri) {
+            Some(doc) => doc.clone(),
+            None => return,
+        };
+        self.checker.check(uri, document, delay);
+    }
+}
+
+
+fn completion_kind(kind: DeclKind) -> CompletionItemKind {
+    match kind {
+        DeclKind::Function => CompletionItemKind::Function,
+        DeclKind::Param | DeclKind::Var | DeclKind::Static => CompletionItemKind::Variable,
+        DeclKind::Local => CompletionItemKind::Field,
+        DeclKind::StaticConst => CompletionItemKind::Constant,
+    }
+}
+
+/// Kind of the declaration behind a global index symbol.
+fn decl_kind(kind: SymbolKind) -> DeclKind {
+    if kind == SymbolKind::Constant { DeclKind::StaticConst } else { DeclKind::Function }
+}
+
+/// The `planetPath` setting, pointing to the `planet` directory of an
+/// OpenClonk installation.
+fn planet_path(settings: &serde_json::Value) -> Option<PathBuf> {
+    settings.get("planetPath")
+        .and_then(|path| path.as_str())
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Maps definition IDs to the scripts appending to them.
+fn appendto_map(files: &[Rc<ScriptFile>]) -> HashMap<String, Vec<Url>> {
+    let mut result: HashMap<String, Vec<Url>> = HashMap::new();
+    for file in files {
+        for id in file.script.directive_arguments(&ast::DirectiveKind::Appendto) {
+            result.entry(id.name.clone()).or_default().push(file.uri.clone());
+        }
+    }
+    result
+}
+
+fn location_lens(uri: &Url, range: Range, title: String, locations: Vec<Location>) -> CodeLens {
+    CodeLens {
+        range,
+        command: Some(Command {
+            title,
+            command: "editor.action.showReferences".to_string(),
+            arguments: Some(vec![
+                serde_json::to_value(uri).unwrap(),
+                serde_json::to_value(range.start).unwrap(),
+                serde_json::to_value(locations).unwrap(),
+            ]),
+        }),
+        data: None,
+    }
+}
+
+/// Call hierarchy item for a function of a script.
+fn call_item(file: &ScriptFile, f: &ast::Function) -> CallHierarchyItem {
+    let index = LineIndex::new(&file.code);
+    // Function literals don't have a name.
+    let (name, selection) = if f.name.name.is_empty() {
+        ("func".to_string(), Span::new(f.span.start, f.span.start))
+    } else {
+        (f.name.name.clone(), f.name.span)
+    };
+    CallHierarchyItem {
+        name,
+        kind: if f.visibility == Some(ast::Visibility::Global) { SymbolKind::Function } else { SymbolKind::Method },
+        tags: None,
+        detail: script_name(&file.uri),
+        uri: file.uri.clone(),
+        range: index.range(f.span),
+        selection_range: index.range(selection),
+    }
+}
+
+/// Short description of where a script comes from, e.g. `Clonk.ocd`.
+fn script_name(uri: &Url) -> Option<String> {
+    let path = vfs::to_path(uri)?;
+    let dir = path.parent()?.file_name()?;
+    Some(dir.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempTree;
+
+    fn app(tree: &TempTree) -> App {
+        let (conn, _client) = Connection::memory();
+        App::new(conn, Workspace::new(vec![tree.path().to_path_buf()]), false)
+    }
+
+    fn uri(tree: &TempTree, path: &str) -> Url {
+        Url::from_file_path(tree.path().join(path)).unwrap()
+    }
+
+    /// Changed files relative to the tree with the start of each edit.
+    type Changes = Vec<(String, Vec<(u64, u64)>)>;
+
+    fn rename(app: &mut App, tree: &TempTree, path: &str, line: u64, character: u64) -> Result<Changes, String> {
+        let params = RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri(tree, path) },
+                position: Position::new(line, character),
+            },
+            new_name: "Renamed".to_string(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+        let changes = app.rename(params).map_err(|err| err.to_string())?;
+        let mut changes: Changes = changes.into_iter()
+            .map(|(uri, edits)| {
+                let path = uri.to_file_path().unwrap();
+                let path = path.strip_prefix(tree.path()).unwrap().to_string_lossy().into_owned();
+                (path, edits.iter().map(|e| (e.range.start.line, e.range.start.character)).collect())
+            })
+            .collect();
+        changes.sort();
+        Ok(changes)
+    }
+
+    #[test]
+    fn renames_variables_declared_in_their_own_definition() {
+        let tree = TempTree::new("rename-own", &[
+            ("Objects.ocd/Apple.ocd/DefCore.txt", "[DefCore]\nid=Apple\n"),
+            ("Objects.ocd/Apple.ocd/Script.c", "static Size;\n"),
+            ("Objects.ocd/Rock.ocd/DefCore.txt", "[DefCore]\nid=Rock\n"),
+            ("Objects.ocd/Rock.ocd/Script.c", "local Size;\n\nfunc f()\n{\n\treturn Size;\n}\n"),
+        ]);
+        let mut app = app(&tree);
+        assert_eq!(rename(&mut app, &tree, "Objects.ocd/Rock.ocd/Script.c", 4, 9), Ok(vec![
+            ("Objects.ocd/Rock.ocd/Script.c".to_string(), vec![(0, 6), (4, 8)]),
+        ]));
+    }
+
+    #[test]
+    fn renames_locals_in_definitions_having_them() {
+        let tree = TempTree::new("rename-local", &[
+            ("Objects.ocd/Rock.ocd/DefCore.txt", "[DefCore]\nid=Rock\n"),
+            ("Objects.ocd/Rock.ocd/Script.c", "local count;\n"),
+            ("Objects.ocd/Gold.ocd/DefCore.txt", "[DefCore]\nid=Gold\n"),
+            ("Objects.ocd/Gold.ocd/Script.c", "#include Rock\n\nfunc f()\n{\n\treturn count;\n}\n"),
+            ("Objects.ocd/Tree.ocd/DefCore.txt", "[DefCore]\nid=Tree\n"),
+            ("Objects.ocd/Tree.ocd/Script.c", "local count;\n\nfunc f()\n{\n\treturn count;\n}\n"),
+        ]);
+        let mut app = app(&tree);
+        assert_eq!(rename(&mut app, &tree, "Objects.ocd/Rock.ocd/Script.c", 0, 7), Ok(vec![
+            ("Objects.ocd/Gold.ocd/Script.c".to_string(), vec![(4, 8)]),
+            ("Objects.ocd/Rock.ocd/Script.c".to_string(), vec![(0, 6)]),
+        ]));
+    }
+
+    #[test]
+    fn refuses_to_rename_locals_used_as_properties() {
+        let tree = TempTree::new("rename-property", &[
+            ("Objects.ocd/Rock.ocd/DefCore.txt", "[DefCore]\nid=Rock\n"),
+            ("Objects.ocd/Rock.ocd/Script.c", "local Name;\n"),
+            ("Objects.ocd/Tree.ocd/DefCore.txt", "[DefCore]\nid=Tree\n"),
+            ("Objects.ocd/Tree.ocd/Script.c", "local ActMap = {\n\tFall = { Name = \"Fall\" },\n};\n"),
+        ]);
+        let mut app = app(&tree);
+        assert!(rename(&mut app, &tree, "Objects.ocd/Rock.ocd/Script.c", 0, 7).is_err());
+    }
+
+    /// A definition including two others, appended to by a script that
+    /// includes yet another definition.
+    fn inheritance_tree(name: &str) -> TempTree {
+        TempTree::new(name, &[
+            ("Objects.ocd/Base.ocd/DefCore.txt", "[DefCore]\nid=Base\n"),
+            ("Objects.ocd/Base.ocd/Script.c", "func Hit() {}\n"),
+            ("Objects.ocd/Mixin.ocd/DefCore.txt", "[DefCore]\nid=Mixin\n"),
+            ("Objects.ocd/Mixin.ocd/Script.c", "func Hit() {}\n"),
+            ("Objects.ocd/Extra.ocd/DefCore.txt", "[DefCore]\nid=Extra\n"),
+            ("Objects.ocd/Extra.ocd/Script.c", "func Other() {}\n"),
+            ("Objects.ocd/Rock.ocd/DefCore.txt", "[DefCore]\nid=Rock\n"),
+            ("Objects.ocd/Rock.ocd/Script.c", "#include Base\n#include Mixin\n\nfunc Hit() { return _inherited(); }\n"),
+            ("System.ocg/Patch.c", "#appendto Rock\n#include Extra\n\nfunc Hit() { return _inherited(); }\n"),
+        ])
+    }
+
+    #[test]
+    fn orders_appends_before_includes() {
+        let tree = inheritance_tree("override-order");
+        let mut app = app(&tree);
+        let rock = uri(&tree, "Objects.ocd/Rock.ocd/Script.c");
+        let patch = uri(&tree, "System.ocg/Patch.c");
+        let order: Vec<String> = app.override_order(&rock).iter().map(|f| app.inheritance_item(&f.uri).name).collect();
+        assert_eq!(order, vec!["System.ocg/Patch.c", "Rock", "Extra", "Mixin", "Base"]);
+        let order: Vec<Url> = app.override_order(&patch).iter().map(|f| f.uri.clone()).collect();
+        assert_eq!(order.first(), Some(&patch));
+        assert_eq!(order.len(), 5);
+
+        let inherited = |app: &mut App, uri: &Url| app.inherited_function(uri, "Hit").map(|l| (l.uri, l.range.start.line));
+        assert_eq!(inherited(&mut app, &patch), Some((rock.clone(), 3)));
+        let mixin = uri(&tree, "Objects.ocd/Mixin.ocd/Script.c");
+        assert_eq!(inherited(&mut app, &rock), Some((mixin.clone(), 0)));
+        assert_eq!(inherited(&mut app, &mixin), None);
+    }
+
+    fn lens_titles(app: &mut App, uri: &Url) -> Vec<String> {
+        app.code_lenses(uri).unwrap().into_iter().map(|lens| lens.command.unwrap().title).collect()
+    }
+
+    fn notify<P: serde::Serialize>(app: &mut App, method: &str, params: P) {
+        app.handle_notification(Notification::new(method.to_string(), params)).unwrap();
+    }
+
+    #[test]
+    fn shows_overridden_functions_until_changed() {
+        let tree = inheritance_tree("code-lenses");
+        let mut app = app(&tree);
+        let rock = uri(&tree, "Objects.ocd/Rock.ocd/Script.c");
+        let patch = uri(&tree, "System.ocg/Patch.c");
+        assert_eq!(lens_titles(&mut app, &rock), vec!["overrides Mixin.Hit", "overridden in System.ocg/Patch.c"]);
+        assert_eq!(lens_titles(&mut app, &patch), vec!["overrides Rock.Hit"]);
+
+        notify(&mut app, DidOpenTextDocument::METHOD, DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: patch.clone(),
+                language_id: "c4script".to_string(),
+                version: 1,
+                text: std::fs::read_to_string(patch.to_file_path().unwrap()).unwrap(),
+            },
+        });
+        notify(&mut app, DidChangeTextDocument::METHOD, DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier { uri: patch.clone(), version: Some(2) },
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "#appendto Rock\n".to_string(),
+            }],
+        });
+        assert_eq!(lens_titles(&mut app, &rock), vec!["overrides Mixin.Hit"]);
+        let order: Vec<String> = app.override_order(&rock).iter().map(|f| app.inheritance_item(&f.uri).name).collect();
+        assert_eq!(order, vec!["System.ocg/Patch.c", "Rock", "Mixin", "Base"]);
     }
 }
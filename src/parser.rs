@@ -0,0 +1,656 @@
+//! Error-tolerant parser for C4Script.
+//!
+//! The engine does the real checking; this parser only needs to produce a tree
+//! good enough for navigation while the user is typing. Unexpected tokens are
+//! skipped instead of aborting.
+
+use crate::ast::*;
+use crate::lexer::{self, Span, Token, TokenKind};
+
+/// Parses a script, never failing.
+pub fn parse(src: &str) -> Script {
+    let mut comments = Vec::new();
+    let mut tokens = Vec::new();
+    for token in lexer::tokenize(src) {
+        if token.is_comment() {
+            comments.push(token.span);
+        } else {
+            tokens.push(token);
+        }
+    }
+    let mut parser = Parser { src, tokens, pos: 0 };
+    let mut script = parser.script();
+    script.comments = comments;
+    script
+}
+
+fn binary_precedence(op: &str) -> Option<(u8, bool)> {
+    Some(match op {
+        "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "&=" | "|=" | "^=" | "<<=" | ">>=" | "??=" => (1, true),
+        "??" => (2, true),
+        "||" => (3, false),
+        "&&" => (4, false),
+        "|" => (5, false),
+        "^" => (6, false),
+        "&" => (7, false),
+        "==" | "!=" => (8, false),
+        "<" | "<=" | ">" | ">=" => (9, false),
+        "<<" | ">>" => (10, false),
+        "+" | "-" => (11, false),
+        "*" | "/" | "%" => (12, false),
+        "**" => (13, true),
+        _ => return None,
+    })
+}
+
+fn visibility(word: &str) -> Option<Visibility> {
+    Some(match word {
+        "public" => Visibility::Public,
+        "protected" => Visibility::Protected,
+        "private" => Visibility::Private,
+        "global" => Visibility::Global,
+        _ => return None,
+    })
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek_nth(&self, n: usize) -> Option<Token> {
+        self.tokens.get(self.pos + n).copied()
+    }
+
+    fn text_nth(&self, n: usize) -> &'a str {
+        self.peek_nth(n).map_or("", |t| t.span.text(self.src))
+    }
+
+    fn text(&self) -> &'a str {
+        self.text_nth(0)
+    }
+
+    fn at_ident(&self) -> bool {
+        self.peek_nth(0).map_or(false, |t| t.kind == TokenKind::Ident)
+    }
+
+    fn at_eof(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    /// Start of the next token, or end of input.
+    fn start(&self) -> usize {
+        self.peek_nth(0).map_or(self.src.len(), |t| t.span.start)
+    }
+
+    /// End of the last consumed token.
+    fn last_end(&self) -> usize {
+        if self.pos == 0 {
+            0
+        } else {
+            self.tokens[self.pos - 1].span.end
+        }
+    }
+
+    fn bump(&mut self) -> Option<Token> {
+        let token = self.peek_nth(0);
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        if self.text() == text {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Option<Ident> {
+        if self.at_ident() {
+            let token = self.bump().unwrap();
+            Some(Ident { name: token.span.text(self.src).to_string(), span: token.span })
+        } else {
+            None
+        }
+    }
+
+    /// Whether a function definition starts at the current token.
+    fn at_function(&self) -> bool {
+        self.text() == "func" || (visibility(self.text()).is_some() && self.text_nth(1) == "func")
+    }
+
+    fn script(&mut self) -> Script {
+        let mut script = Script::default();
+        while let Some(token) = self.peek_nth(0) {
+            if token.kind == TokenKind::Directive {
+                self.bump();
+                script.directives.push(self.directive(token));
+            } else if self.at_function() {
+                script.items.push(Item::Function(self.function()));
+            } else if self.text() == "local" || self.text() == "static" {
+                script.items.push(Item::Vars(self.var_decl()));
+                self.eat(";");
+            } else {
+                self.bump();
+            }
+        }
+        script
+    }
+
+    fn directive(&self, token: Token) -> Directive {
+        let text = token.span.text(self.src);
+        let mut words = text.split_whitespace();
+        let kind = match words.next().unwrap_or("") {
+            "#include" => DirectiveKind::Include,
+            "#appendto" => DirectiveKind::Appendto,
+            other => DirectiveKind::Other(other.trim_start_matches('#').to_string()),
+        };
+        let argument = words.next().map(|word| {
+            // `split_whitespace` returns subslices, so the offset can be recovered.
+            let start = token.span.start + (word.as_ptr() as usize - text.as_ptr() as usize);
+            Ident { name: word.to_string(), span: Span::new(start, start + word.len()) }
+        });
+        Directive { kind, argument, span: token.span }
+    }
+
+    fn function(&mut self) -> Function {
+        let start = self.start();
+        let vis = visibility(self.text());
+        if vis.is_some() {
+            self.bump();
+        }
+        self.eat("func");
+        let name = self.ident().unwrap_or_else(|| Ident { name: String::new(), span: Span::new(self.start(), self.start()) });
+        self.function_rest(start, vis, name)
+    }
+
+    /// Parses parameters and body of a function.
+    fn function_rest(&mut self, start: usize, visibility: Option<Visibility>, name: Ident) -> Function {
+        let mut params = Vec::new();
+        if self.eat("(") {
+            while !self.at_eof() && !self.eat(")") {
+                if self.text() == "{" {
+                    break;
+                }
+                let first = self.ident();
+                match (first, self.ident()) {
+                    (Some(ty), Some(name)) => params.push(Param { ty: Some(ty), name }),
+                    (Some(name), None) => params.push(Param { ty: None, name }),
+                    _ => if self.text() != ")" {
+                        self.bump();
+                    },
+                }
+                self.eat(",");
+            }
+        }
+        let body = if self.text() == "{" {
+            self.block()
+        } else {
+            Block { stmts: Vec::new(), span: Span::new(self.last_end(), self.last_end()) }
+        };
+        Function { visibility, name, params, span: Span::new(start, body.span.end), body }
+    }
+
+    fn var_decl(&mut self) -> VarDecl {
+        let start = self.start();
+        let scope = match self.bump().map(|t| t.span.text(self.src)) {
+            Some("local") => VarScope::Local,
+            Some("static") => if self.eat("const") { VarScope::StaticConst } else { VarScope::Static },
+            _ => VarScope::Var,
+        };
+        let mut vars = Vec::new();
+        while let Some(name) = self.ident() {
+            let value = if self.eat("=") { Some(self.expr()) } else { None };
+            vars.push(VarInit { name, value });
+            if !self.eat(",") {
+                break;
+            }
+        }
+        VarDecl { scope, vars, span: Span::new(start, self.last_end().max(start)) }
+    }
+
+    fn block(&mut self) -> Block {
+        let start = self.start();
+        self.eat("{");
+        let mut stmts = Vec::new();
+        while !self.at_eof() && self.text() != "}" {
+            // A new function means the closing brace is missing.
+            if self.at_function() && self.text_nth(1) != "(" {
+                break;
+            }
+            let pos = self.pos;
+            stmts.push(self.stmt());
+            if self.pos == pos {
+                self.bump();
+            }
+        }
+        self.eat("}");
+        Block { stmts, span: Span::new(start, self.last_end()) }
+    }
+
+    fn paren_expr(&mut self) -> Expr {
+        self.eat("(");
+        let e = self.expr();
+        self.eat(")");
+        e
+    }
+
+    fn sub_stmt(&mut self) -> Box<Stmt> {
+        Box::new(self.stmt())
+    }
+
+    fn stmt(&mut self) -> Stmt {
+        let start = self.start();
+        let kind = match self.text() {
+            "{" => StmtKind::Block(self.block()),
+            ";" => {
+                self.bump();
+                StmtKind::Empty
+            },
+            "var" => {
+                let v = self.var_decl();
+                self.eat(";");
+                StmtKind::Vars(v)
+            },
+            "if" => {
+                self.bump();
+                let cond = self.paren_expr();
+                let then = self.sub_stmt();
+                let otherwise = if self.eat("else") { Some(self.sub_stmt()) } else { None };
+                StmtKind::If { cond, then, otherwise }
+            },
+            "while" => {
+                self.bump();
+                let cond = self.paren_expr();
+                StmtKind::While { cond, body: self.sub_stmt() }
+            },
+            "do" => {
+                self.bump();
+                let body = self.sub_stmt();
+                self.eat("while");
+                let cond = self.paren_expr();
+                self.eat(";");
+                StmtKind::DoWhile { body, cond }
+            },
+            "for" => {
+                self.bump();
+                self.for_stmt()
+            },
+            "return" => {
+                self.bump();
+                let value = if self.text() == ";" || self.text() == "}" { None } else { Some(self.expr()) };
+                self.eat(";");
+                StmtKind::Return(value)
+            },
+            "break" | "continue" => {
+                let kind = if self.text() == "break" { StmtKind::Break } else { StmtKind::Continue };
+                self.bump();
+                self.eat(";");
+                kind
+            },
+            _ => {
+                let e = self.expr();
+                self.eat(";");
+                StmtKind::Expr(e)
+            },
+        };
+        Stmt { kind, span: Span::new(start, self.last_end().max(start)) }
+    }
+
+    fn for_stmt(&mut self) -> StmtKind {
+        self.eat("(");
+        let decl = self.text() == "var";
+        let skip = if decl { 1 } else { 0 };
+        if self.peek_nth(skip).map_or(false, |t| t.kind == TokenKind::Ident) && self.text_nth(skip + 1) == "in" {
+            if decl {
+                self.bump();
+            }
+            let var = self.ident().unwrap();
+            self.bump();
+            let iter = self.expr();
+            self.eat(")");
+            return StmtKind::ForIn { var, decl, iter, body: self.sub_stmt() };
+        }
+        let init = if self.text() == ";" {
+            self.bump();
+            None
+        } else {
+            Some(self.sub_stmt())
+        };
+        let cond = if self.text() == ";" { None } else { Some(self.expr()) };
+        self.eat(";");
+        let step = if self.text() == ")" { None } else { Some(self.expr()) };
+        self.eat(")");
+        StmtKind::For { init, cond, step, body: self.sub_stmt() }
+    }
+
+    fn expr(&mut self) -> Expr {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, min_prec: u8) -> Expr {
+        let mut lhs = self.unary();
+        loop {
+            let op = self.text();
+            let (prec, right) = match binary_precedence(op) {
+                Some(p) if p.0 >= min_prec => p,
+                _ => break,
+            };
+            self.bump();
+            let rhs = self.binary(if right { prec } else { prec + 1 });
+            lhs = Expr {
+                span: lhs.span.to(rhs.span),
+                kind: ExprKind::Binary { lhs: Box::new(lhs), rhs: Box::new(rhs) },
+            };
+        }
+        lhs
+    }
+
+    fn unary(&mut self) -> Expr {
+        match self.text() {
+            "!" | "~" | "-" | "+" | "++" | "--" => {
+                let start = self.start();
+                self.bump();
+                let operand = self.unary();
+                Expr {
+                    span: Span::new(start, operand.span.end),
+                    kind: ExprKind::Unary { operand: Box::new(operand) },
+                }
+            },
+            _ => {
+                let primary = self.primary();
+                self.postfix(primary)
+            },
+        }
+    }
+
+    fn postfix(&mut self, mut e: Expr) -> Expr {
+        loop {
+            let start = e.span.start;
+            match self.text() {
+                op @ "->" | op @ "->~" => {
+                    self.bump();
+                    let name = match self.ident() {
+                        Some(name) => name,
+                        None => Ident { name: String::new(), span: Span::new(self.last_end(), self.last_end()) },
+                    };
+                    let args = if self.text() == "(" { self.args() } else { Vec::new() };
+                    e = Expr {
+                        span: Span::new(start, self.last_end()),
+                        kind: ExprKind::MethodCall { target: Box::new(e), fail_safe: op == "->~", name, args },
+                    };
+                },
+                "." => {
+                    self.bump();
+                    let name = match self.ident() {
+                        Some(name) => name,
+                        None => Ident { name: String::new(), span: Span::new(self.last_end(), self.last_end()) },
+                    };
+                    e = if self.text() == "(" {
+                        let args = self.args();
+                        Expr {
+                            span: Span::new(start, self.last_end()),
+                            kind: ExprKind::MethodCall { target: Box::new(e), fail_safe: false, name, args },
+                        }
+                    } else {
+                        Expr {
+                            span: Span::new(start, self.last_end()),
+                            kind: ExprKind::Member { target: Box::new(e), name },
+                        }
+                    };
+                },
+                "[" => {
+                    self.bump();
+                    let index = self.expr();
+                    self.eat("]");
+                    e = Expr {
+                        span: Span::new(start, self.last_end()),
+                        kind: ExprKind::Index { target: Box::new(e), index: Box::new(index) },
+                    };
+                },
+                "++" | "--" => {
+                    self.bump();
+                    e = Expr {
+                        span: Span::new(start, self.last_end()),
+                        kind: ExprKind::Unary { operand: Box::new(e) },
+                    };
+                },
+                _ => break,
+            }
+        }
+        e
+    }
+
+    /// Parses a parenthesized argument list. Empty arguments are skipped.
+    fn args(&mut self) -> Vec<Expr> {
+        let mut args = Vec::new();
+        self.eat("(");
+        loop {
+            match self.text() {
+                ")" => {
+                    self.bump();
+                    break;
+                },
+                "," => {
+                    self.bump();
+                },
+                "" | ";" | "}" => break,
+                _ => {
+                    let pos = self.pos;
+                    args.push(self.expr());
+                    if self.pos == pos {
+                        break;
+                    }
+                },
+            }
+        }
+        args
+    }
+
+    fn primary(&mut self) -> Expr {
+        let start = self.start();
+        let token = match self.peek_nth(0) {
+            Some(token) => token,
+            None => return Expr { kind: ExprKind::Error, span: Span::new(start, start) },
+        };
+        let kind = match token.kind {
+            TokenKind::Number => {
+                self.bump();
+                ExprKind::Number
+            },
+            TokenKind::String => {
+                self.bump();
+                ExprKind::String
+            },
+            TokenKind::Ident => match self.text() {
+                "true" | "false" | "nil" | "this" => {
+                    self.bump();
+                    // Old-style `this()`
+                    if self.text() == "(" && self.text_nth(1) == ")" {
+                        self.pos += 2;
+                    }
+                    ExprKind::Constant
+                },
+                "func" => {
+                    self.bump();
+                    let name = Ident { name: String::new(), span: Span::new(start, start) };
+                    ExprKind::Function(Box::new(self.function_rest(start, None, name)))
+                },
+                "new" => {
+                    self.bump();
+                    let prototype = self.ident();
+                    return self.proplist(start, prototype);
+                },
+                _ => {
+                    let name = self.ident().unwrap();
+                    if self.text() == "(" {
+                        ExprKind::Call { name, args: self.args() }
+                    } else {
+                        ExprKind::Ident(name)
+                    }
+                },
+            },
+            _ => match self.text() {
+                "(" => {
+                    self.bump();
+                    let inner = self.expr();
+                    self.eat(")");
+                    ExprKind::Paren(Box::new(inner))
+                },
+                "[" => {
+                    self.bump();
+                    let mut items = Vec::new();
+                    while !self.at_eof() && !self.eat("]") {
+                        if self.eat(",") {
+                            continue;
+                        }
+                        if self.text() == ";" || self.text() == "}" {
+                            break;
+                        }
+                        let pos = self.pos;
+                        items.push(self.expr());
+                        if self.pos == pos {
+                            break;
+                        }
+                    }
+                    ExprKind::Array(items)
+                },
+                "{" => return self.proplist(start, None),
+                // `$Name$` string table reference
+                "$" if self.peek_nth(1).map_or(false, |t| t.kind == TokenKind::Ident) && self.text_nth(2) == "$" => {
+                    self.pos += 3;
+                    ExprKind::String
+                },
+                ")" | "]" | "}" | ";" | "," => ExprKind::Error,
+                _ => {
+                    self.bump();
+                    ExprKind::Error
+                },
+            },
+        };
+        Expr { kind, span: Span::new(start, self.last_end().max(start)) }
+    }
+
+    fn proplist(&mut self, start: usize, prototype: Option<Ident>) -> Expr {
+        let mut props = Vec::new();
+        if self.eat("{") {
+            while !self.at_eof() && !self.eat("}") {
+                if self.eat(",") {
+                    continue;
+                }
+                let key_start = self.start();
+                let key = match self.peek_nth(0) {
+                    Some(t) if t.kind == TokenKind::Ident || t.kind == TokenKind::String => {
+                        self.bump();
+                        let name = t.span.text(self.src).trim_matches('"').to_string();
+                        Ident { name, span: t.span }
+                    },
+                    _ => {
+                        // Give up on this proplist, the surrounding statement will recover.
+                        if self.text() == ";" {
+                            break;
+                        }
+                        self.bump();
+                        continue;
+                    },
+                };
+                if !(self.eat("=") || self.eat(":")) {
+                    continue;
+                }
+                let value = self.expr();
+                props.push(Property { key, span: Span::new(key_start, value.span.end), value });
+            }
+        }
+        Expr {
+            kind: ExprKind::Proplist { prototype, props },
+            span: Span::new(start, self.last_end().max(start)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn functions(script: &Script) -> Vec<&Function> {
+        script.items.iter()
+            .filter_map(|item| match item {
+                Item::Function(f) => Some(f),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn names(script: &Script) -> Vec<&str> {
+        functions(script).iter().map(|f| f.name.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_declarations() {
+        let src = "#include Library_Foo\n#appendto Rock\nlocal Name = \"Rock\";\nstatic const Max = 3;\n\
+            public func Hit(int speed, object by) { return speed; }\nglobal func Log2() {}\n";
+        let script = parse(src);
+        let includes: Vec<&str> = script.includes().map(|id| id.name.as_str()).collect();
+        assert_eq!(includes, ["Library_Foo", "Rock"]);
+        assert_eq!(script.directives[0].argument.as_ref().unwrap().span.text(src), "Library_Foo");
+        assert_eq!(names(&script), ["Hit", "Log2"]);
+        let hit = functions(&script)[0];
+        assert_eq!(hit.visibility, Some(Visibility::Public));
+        assert_eq!(hit.params.iter().map(|p| p.name.name.as_str()).collect::<Vec<_>>(), ["speed", "by"]);
+        assert_eq!(hit.params[0].ty.as_ref().unwrap().name, "int");
+        assert_eq!(hit.span.text(src), "public func Hit(int speed, object by) { return speed; }");
+        let scopes: Vec<VarScope> = script.items.iter()
+            .filter_map(|item| match item {
+                Item::Vars(v) => Some(v.scope),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(scopes, [VarScope::Local, VarScope::StaticConst]);
+    }
+
+    #[test]
+    fn recovers_from_missing_closing_brace() {
+        let script = parse("func a() {\n\tif (x) {\n\t\tFoo(\n}\nfunc b() {\n\tBar();\n}\n");
+        assert_eq!(names(&script), ["a", "b"]);
+        assert_eq!(functions(&script)[1].body.stmts.len(), 1);
+    }
+
+    #[test]
+    fn recovers_from_unfinished_statements() {
+        let src = "func a() {\n\tvar x = \n\tobj->\n\tFoo(1, ;\n\tBar()\n\treturn x;\n}\nfunc b() {}\n";
+        let script = parse(src);
+        assert_eq!(names(&script), ["a", "b"]);
+        let mut calls = Vec::new();
+        script.walk(&mut |node| match node {
+            Node::Expr(Expr { kind: ExprKind::Call { name, .. }, .. }) => calls.push(name.name.clone()),
+            Node::Expr(Expr { kind: ExprKind::MethodCall { name, .. }, .. }) => calls.push(format!("->{}", name.name)),
+            _ => (),
+        });
+        // Line breaks don't end statements, so `obj->` continues on the next line.
+        assert_eq!(calls, ["->Foo", "Bar"]);
+        assert!(functions(&script)[0].body.stmts.iter().any(|s| matches!(s.kind, StmtKind::Return(Some(_)))));
+    }
+
+    #[test]
+    fn skips_stray_tokens() {
+        let script = parse("} ) @@ 42 func a() {} ; ]] local x;");
+        assert_eq!(names(&script), ["a"]);
+        assert_eq!(script.items.len(), 2);
+    }
+
+    #[test]
+    fn never_panics_on_prefixes() {
+        let src = "#include Foo\nlocal ActMap = { Walk = { Prototype = Action, Speed = 10 } };\n\
+            func Initialize(int x, ...) {\n\tfor (var i in [1, 2]) x += i;\n\tdo x--; while (x > 0);\n\
+            \tvar f = func(a) { return a ?? $Name$; };\n\treturn this->~Foo(f, { a = 1 })[0].b;\n}\n";
+        for end in (0..=src.len()).filter(|&i| src.is_char_boundary(i)) {
+            parse(&src[..end]);
+        }
+    }
+}
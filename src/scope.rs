@@ -0,0 +1,368 @@
+//! Name resolution on top of the syntax tree.
+
+use crate::ast::*;
+use crate::engine;
+use crate::lexer::Span;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeclKind {
+    Function,
+    Param,
+    Var,
+    Local,
+    Static,
+    StaticConst,
+}
+
+impl From<VarScope> for DeclKind {
+    fn from(scope: VarScope) -> DeclKind {
+        match scope {
+            VarScope::Var => DeclKind::Var,
+            VarScope::Local => DeclKind::Local,
+            VarScope::Static => DeclKind::Static,
+            VarScope::StaticConst => DeclKind::StaticConst,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Declaration {
+    pub name: String,
+    pub kind: DeclKind,
+    /// Span of the declared name.
+    pub span: Span,
+}
+
+impl Declaration {
+    fn new(ident: &Ident, kind: DeclKind) -> Declaration {
+        Declaration { name: ident.name.clone(), kind, span: ident.span }
+    }
+}
+
+/// How an identifier is used at a particular place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefKind {
+    /// The declaration of the name itself.
+    Declaration(DeclKind),
+    /// Plain variable access, `Foo`.
+    Variable,
+    /// Plain function call, `Foo()`.
+    Call,
+    /// `obj->Foo()`
+    MethodCall,
+    /// `obj.Foo`
+    Member,
+    /// Key in a proplist literal, `{ Foo = 1 }`.
+    PropertyKey,
+    /// Argument of `#include` or `#appendto`.
+    Include,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Occurrence<'a> {
+    pub ident: &'a Ident,
+    pub kind: RefKind,
+}
+
+/// Lists all identifiers in the script in source order.
+pub fn occurrences(script: &Script) -> Vec<Occurrence> {
+    let mut result = Vec::new();
+    script.walk(&mut |node| {
+        let mut push = |ident, kind| result.push(Occurrence { ident, kind });
+        match node {
+            Node::Directive(Directive { kind: DirectiveKind::Include, argument: Some(arg), .. })
+            | Node::Directive(Directive { kind: DirectiveKind::Appendto, argument: Some(arg), .. }) => {
+                push(arg, RefKind::Include);
+            },
+            Node::Function(f) => push(&f.name, RefKind::Declaration(DeclKind::Function)),
+            Node::Param(p) => push(&p.name, RefKind::Declaration(DeclKind::Param)),
+            Node::VarDecl(v) => for var in &v.vars {
+                push(&var.name, RefKind::Declaration(v.scope.into()));
+            },
+            Node::Stmt(Stmt { kind: StmtKind::ForIn { var, decl, .. }, .. }) => {
+                push(var, if *decl { RefKind::Declaration(DeclKind::Var) } else { RefKind::Variable });
+            },
+            Node::Expr(e) => match &e.kind {
+                ExprKind::Ident(name) => push(name, RefKind::Variable),
+                ExprKind::Call { name, .. } => push(name, RefKind::Call),
+                ExprKind::MethodCall { name, .. } => push(name, RefKind::MethodCall),
+                ExprKind::Member { name, .. } => push(name, RefKind::Member),
+                _ => (),
+            },
+            Node::Property(p) => push(&p.key, RefKind::PropertyKey),
+            _ => (),
+        }
+    });
+    result.retain(|o| !o.ident.name.is_empty());
+    result.sort_by_key(|o| o.ident.span.start);
+    result
+}
+
+/// Returns the identifier at the given offset.
+pub fn occurrence_at(script: &Script, offset: usize) -> Option<Occurrence> {
+    occurrences(script).into_iter().find(|o| o.ident.span.contains(offset))
+}
+
+/// Returns the innermost function containing the offset.
+pub fn enclosing_function(script: &Script, offset: usize) -> Option<&Function> {
+    let mut result = None;
+    script.walk(&mut |node| if let Node::Function(f) = node {
+        if f.span.contains(offset) {
+            result = Some(f);
+        }
+    });
+    result
+}
+
+/// Declarations visible in the whole script: functions, locals and statics.
+pub fn script_declarations(script: &Script) -> Vec<Declaration> {
+    let mut result = Vec::new();
+    for item in &script.items {
+        match item {
+            Item::Function(f) => if !f.name.name.is_empty() {
+                result.push(Declaration::new(&f.name, DeclKind::Function));
+            },
+            Item::Vars(v) => result.extend(v.vars.iter().map(|var| Declaration::new(&var.name, v.scope.into()))),
+        }
+    }
+    result
+}
+
+/// Declarations that are visible from every script: `global func` and statics.
+pub fn global_declarations(script: &Script) -> Vec<Declaration> {
+    let mut result = Vec::new();
+    for item in &script.items {
+        match item {
+            Item::Function(f) => if f.visibility == Some(Visibility::Global) {
+                result.push(Declaration::new(&f.name, DeclKind::Function));
+            },
+            Item::Vars(v) => if v.scope != VarScope::Local {
+                result.extend(v.vars.iter().map(|var| Declaration::new(&var.name, v.scope.into())));
+            },
+        }
+    }
+    result
+}
+
+/// Parameters and `var` declarations of a function. Function literals nested
+/// inside have their own scope and are skipped.
+pub fn function_locals(f: &Function) -> Vec<Declaration> {
+    fn visit(node: Node, result: &mut Vec<Declaration>) {
+        match node {
+            Node::Expr(Expr { kind: ExprKind::Function(_), .. }) => return,
+            Node::VarDecl(v) if v.scope == VarScope::Var => {
+                result.extend(v.vars.iter().map(|var| Declaration::new(&var.name, DeclKind::Var)));
+            },
+            Node::Stmt(Stmt { kind: StmtKind::ForIn { var, decl: true, .. }, .. }) => {
+                result.push(Declaration::new(var, DeclKind::Var));
+            },
+            _ => (),
+        }
+        for child in node.children() {
+            visit(child, result);
+        }
+    }
+    let mut result: Vec<Declaration> = f.params.iter().map(|p| Declaration::new(&p.name, DeclKind::Param)).collect();
+    visit(Node::Block(&f.body), &mut result);
+    result
+}
+
+/// Resolves a name to a parameter or `var` of the function around `offset`.
+/// Since `var` is function-scoped, a declaration after the use is accepted
+/// if there is none before.
+pub fn resolve_local(script: &Script, offset: usize, name: &str) -> Option<Declaration> {
+    let f = enclosing_function(script, offset)?;
+    let candidates: Vec<Declaration> = function_locals(f).into_iter().filter(|d| d.name == name).collect();
+    candidates.iter().rev().find(|d| d.span.start <= offset).or_else(|| candidates.first()).cloned()
+}
+
+/// If the identifier at `offset` is a parameter or `var`, returns all of its
+/// occurrences, which are confined to the enclosing function.
+pub fn local_occurrences(script: &Script, offset: usize) -> Option<Vec<Occurrence>> {
+    let occurrence = occurrence_at(script, offset)?;
+    let name = &occurrence.ident.name;
+    let local = match occurrence.kind {
+        RefKind::Declaration(DeclKind::Param) | RefKind::Declaration(DeclKind::Var) => true,
+        RefKind::Variable => resolve_local(script, offset, name).is_some(),
+        _ => false,
+    };
+    if !local {
+        return None;
+    }
+    let f = enclosing_function(script, offset)?;
+    Some(occurrences(script).into_iter()
+        .filter(|o| &o.ident.name == name && f.span.contains(o.ident.span.start))
+        .filter(|o| matches!(o.kind,
+            RefKind::Declaration(DeclKind::Param) | RefKind::Declaration(DeclKind::Var) | RefKind::Variable))
+        .filter(|o| enclosing_function(script, o.ident.span.start).map_or(false, |g| std::ptr::eq(f, g)))
+        .collect())
+}
+
+/// Parameters that are never used in their function. Functions passing on
+/// their arguments with `...` or accessing them with `Par` use all of them,
+/// and names starting with an underscore mark intentionally unused ones.
+pub fn unused_parameters<'a>(script: &'a Script, code: &str) -> Vec<&'a Ident> {
+    let mut functions = Vec::new();
+    script.walk(&mut |node| if let Node::Function(f) = node {
+        functions.push(f);
+    });
+    let occurrences = occurrences(script);
+    let mut result = Vec::new();
+    for f in functions {
+        let body = f.body.span.text(code);
+        if body.contains("...") || body.contains("Par(") {
+            continue;
+        }
+        result.extend(f.params.iter()
+            .filter(|p| !p.name.name.is_empty() && !p.name.name.starts_with('_'))
+            .filter(|p| !occurrences.iter().any(|o| {
+                o.kind == RefKind::Variable && o.ident.name == p.name.name && f.body.span.contains(o.ident.span.start)
+                    && resolve_local(script, o.ident.span.start, &o.ident.name).map_or(false, |d| d.span == p.name.span)
+            }))
+            .map(|p| &p.name));
+    }
+    result
+}
+
+/// Whether an occurrence in `script` can refer to a script-level declaration
+/// of the given kind with the same name. Names of functions, locals and
+/// statics are separate, and variables shadowed by a parameter or `var`
+/// don't count.
+pub fn refers_to(script: &Script, occurrence: &Occurrence, kind: DeclKind) -> bool {
+    match (kind, occurrence.kind) {
+        (DeclKind::Function, RefKind::Declaration(DeclKind::Function))
+            | (DeclKind::Function, RefKind::Call)
+            | (DeclKind::Function, RefKind::MethodCall)
+            | (DeclKind::Local, RefKind::Declaration(DeclKind::Local))
+            | (DeclKind::Local, RefKind::Member)
+            | (DeclKind::Local, RefKind::PropertyKey) => true,
+        (DeclKind::Static, RefKind::Declaration(k))
+            | (DeclKind::StaticConst, RefKind::Declaration(k)) => k == DeclKind::Static || k == DeclKind::StaticConst,
+        (DeclKind::Local, RefKind::Variable)
+            | (DeclKind::Static, RefKind::Variable)
+            | (DeclKind::StaticConst, RefKind::Variable) => {
+            resolve_local(script, occurrence.ident.span.start, &occurrence.ident.name).is_none()
+        },
+        _ => false,
+    }
+}
+
+/// Functions of the script calling the named function, each with the names
+/// in its calls, including calls that are only resolved at runtime.
+pub fn callers<'a>(script: &'a Script, code: &str, name: &str) -> Vec<(&'a Function, Vec<Span>)> {
+    let mut spans: Vec<Span> = occurrences(script).into_iter()
+        .filter(|o| o.ident.name == name && matches!(o.kind, RefKind::Call | RefKind::MethodCall))
+        .map(|o| o.ident.span)
+        .chain(dynamic_references(script, code, name))
+        .collect();
+    spans.sort_by_key(|span| span.start);
+    spans.dedup();
+    let mut callers: Vec<(&Function, Vec<Span>)> = Vec::new();
+    for span in spans {
+        let caller = match enclosing_function(script, span.start) {
+            Some(caller) => caller,
+            None => continue,
+        };
+        match callers.iter_mut().find(|(f, _)| std::ptr::eq(*f, caller)) {
+            Some((_, spans)) => spans.push(span),
+            None => callers.push((caller, vec![span])),
+        }
+    }
+    callers
+}
+
+/// Names of the functions `f` calls directly, each with the names in its
+/// calls.
+pub fn callees(script: &Script, f: &Function) -> Vec<(String, Vec<Span>)> {
+    let mut callees: Vec<(String, Vec<Span>)> = Vec::new();
+    for o in occurrences(script) {
+        // Calls in nested function literals belong to those.
+        if !matches!(o.kind, RefKind::Call | RefKind::MethodCall)
+            || !enclosing_function(script, o.ident.span.start).map_or(false, |g| std::ptr::eq(f, g))
+        {
+            continue;
+        }
+        match callees.iter_mut().find(|(name, _)| name == &o.ident.name) {
+            Some((_, spans)) => spans.push(o.ident.span),
+            None => callees.push((o.ident.name.clone(), vec![o.ident.span])),
+        }
+    }
+    callees
+}
+
+/// Places that may call the named function, but only at runtime: fail-safe
+/// calls `obj->~Foo()` and function names in strings passed to `Call`,
+/// `Schedule` and similar.
+pub fn dynamic_references(script: &Script, code: &str, name: &str) -> Vec<Span> {
+    let is_ident_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut result = Vec::new();
+    script.walk(&mut |node| {
+        let (callee, args) = match node {
+            Node::Expr(Expr { kind: ExprKind::MethodCall { fail_safe: true, name: callee, .. }, .. }) if callee.name == name => {
+                result.push(callee.span);
+                return;
+            },
+            Node::Expr(Expr { kind: ExprKind::Call { name: callee, args }, .. })
+                | Node::Expr(Expr { kind: ExprKind::MethodCall { name: callee, args, .. }, .. }) => (callee, args),
+            _ => return,
+        };
+        if !engine::DYNAMIC_CALLS.contains(&callee.name.as_str()) {
+            return;
+        }
+        for arg in args.iter().filter(|arg| matches!(arg.kind, ExprKind::String)) {
+            let text = arg.span.text(code);
+            for (i, _) in text.match_indices(name) {
+                let before = text[..i].chars().next_back();
+                let after = text[i + name.len()..].chars().next();
+                if !before.map_or(false, is_ident_char) && !after.map_or(false, is_ident_char) {
+                    let start = arg.span.start + i;
+                    result.push(Span::new(start, start + name.len()));
+                }
+            }
+        }
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn finds_dynamic_references() {
+        let code = "func f(obj)\n{\n\tobj->~Foo();\n\tobj->Foo();\n\tCall(\"Foo\");\n\tSchedule(obj, \"Foo(1)\", 10);\n\tLog(\"Foo\");\n\tCall(\"FooBar\");\n}\n";
+        let script = parser::parse(code);
+        let spans = dynamic_references(&script, code, "Foo");
+        let lines: Vec<usize> = spans.iter().map(|span| code[..span.start].matches('\n').count()).collect();
+        assert_eq!(lines, [2, 4, 5]);
+        assert!(spans.iter().all(|span| span.text(code) == "Foo"));
+    }
+
+    #[test]
+    fn finds_callers_and_callees() {
+        let code = "func a()\n{\n\tb();\n\tb(c());\n}\n\nfunc c()\n{\n\tSchedule(this, \"b()\", 1);\n\tthis->~b();\n}\n\nfunc b() {}\n";
+        let script = parser::parse(code);
+        let callers: Vec<(&str, Vec<&str>)> = callers(&script, code, "b").into_iter()
+            .map(|(f, spans)| (f.name.name.as_str(), spans.iter().map(|s| s.text(code)).collect()))
+            .collect();
+        assert_eq!(callers, [("a", vec!["b", "b"]), ("c", vec!["b", "b"])]);
+
+        let a = enclosing_function(&script, 0).unwrap();
+        let callees: Vec<(String, usize)> = callees(&script, a).into_iter()
+            .map(|(name, spans)| (name, spans.len()))
+            .collect();
+        assert_eq!(callees, [("b".to_string(), 2), ("c".to_string(), 1)]);
+    }
+
+    #[test]
+    fn property_keys_refer_to_locals() {
+        let code = "local Foo;\nfunc f()\n{\n\treturn {Foo = 1, Bar = Foo};\n}\n";
+        let script = parser::parse(code);
+        let refs: Vec<RefKind> = occurrences(&script).into_iter()
+            .filter(|o| o.ident.name == "Foo" && refers_to(&script, o, DeclKind::Local))
+            .map(|o| o.kind)
+            .collect();
+        assert_eq!(refs, [RefKind::Declaration(DeclKind::Local), RefKind::PropertyKey, RefKind::Variable]);
+    }
+}
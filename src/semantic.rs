@@ -0,0 +1,202 @@
+//! Semantic tokens: classifying identifiers by what they refer to, which a
+//! TextMate grammar can't do.
+
+use crate::ast::Script;
+use crate::engine;
+use crate::lexer::{self, Span, TokenKind};
+use crate::scope::{self, DeclKind, Declaration, RefKind};
+use crate::utils::LineIndex;
+use regex::Regex;
+
+/// Token types in the order of their indices in the legend.
+pub const TOKEN_TYPES: &[&str] = &["function", "parameter", "variable", "property", "class", "macro"];
+/// Token modifiers in the order of their bits in the legend.
+pub const TOKEN_MODIFIERS: &[&str] = &["declaration", "static", "readonly", "defaultLibrary"];
+
+#[derive(Clone, Copy)]
+enum TokenType {
+    Function,
+    Parameter,
+    Variable,
+    /// A `local` or another property.
+    Property,
+    /// A definition ID.
+    Class,
+    /// A string table reference, `$Name$`.
+    Macro,
+}
+
+const DECLARATION: u32 = 1;
+const STATIC: u32 = 1 << 1;
+const READONLY: u32 = 1 << 2;
+const DEFAULT_LIBRARY: u32 = 1 << 3;
+
+/// What names used in a script can refer to besides its own parameters and
+/// `var`s.
+pub struct Names<'a> {
+    /// Script-level declarations of the script, the scripts it includes and
+    /// global declarations, in order of precedence.
+    pub declarations: Vec<Declaration>,
+    pub is_definition: &'a dyn Fn(&str) -> bool,
+}
+
+/// Classifies the identifiers and string table references of a script,
+/// encoded relative to each other as `textDocument/semanticTokens` expects.
+/// With a span, only tokens overlapping it are returned.
+pub fn semantic_tokens(script: &Script, code: &str, names: &Names, span: Option<Span>) -> Vec<u32> {
+    let mut tokens: Vec<(Span, TokenType, u32)> = scope::occurrences(script).into_iter()
+        .filter_map(|o| {
+            let (ty, modifiers) = classify(script, names, &o)?;
+            Some((o.ident.span, ty, modifiers))
+        })
+        .collect();
+    let string_table_re = Regex::new(r"\$[A-Za-z_][A-Za-z0-9_]*\$").unwrap();
+    for token in lexer::tokenize(code).into_iter().filter(|t| t.kind == TokenKind::String) {
+        tokens.extend(string_table_re.find_iter(token.span.text(code))
+            .map(|m| (Span::new(token.span.start + m.start(), token.span.start + m.end()), TokenType::Macro, 0)));
+    }
+    tokens.retain(|(s, _, _)| span.map_or(true, |span| s.start < span.end && span.start < s.end));
+    tokens.sort_by_key(|(s, _, _)| s.start);
+
+    let index = LineIndex::new(code);
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let (mut prev_line, mut prev_start) = (0, 0);
+    for (span, ty, modifiers) in tokens {
+        let range = index.range(span);
+        // Tokens can't span multiple lines.
+        if range.start.line != range.end.line {
+            continue;
+        }
+        let (line, start) = (range.start.line as u32, range.start.character as u32);
+        let delta_start = if line == prev_line { start - prev_start } else { start };
+        data.extend(&[line - prev_line, delta_start, range.end.character as u32 - start, ty as u32, modifiers]);
+        prev_line = line;
+        prev_start = start;
+    }
+    data
+}
+
+fn classify(script: &Script, names: &Names, occurrence: &scope::Occurrence) -> Option<(TokenType, u32)> {
+    let name = &occurrence.ident.name;
+    let declared = |kinds: &[DeclKind]| names.declarations.iter()
+        .find(|d| &d.name == name && kinds.contains(&d.kind))
+        .map(|d| d.kind);
+    Some(match occurrence.kind {
+        RefKind::Declaration(kind) => {
+            let (ty, modifiers) = decl_token(kind);
+            (ty, modifiers | DECLARATION)
+        },
+        RefKind::Variable => {
+            if let Some(decl) = scope::resolve_local(script, occurrence.ident.span.start, name) {
+                decl_token(decl.kind)
+            } else if let Some(kind) = declared(&[DeclKind::Local, DeclKind::Static, DeclKind::StaticConst]) {
+                decl_token(kind)
+            } else if (names.is_definition)(name) {
+                (TokenType::Class, 0)
+            } else {
+                return None;
+            }
+        },
+        RefKind::Call | RefKind::MethodCall => {
+            // Scripts may overload engine functions.
+            if declared(&[DeclKind::Function]).is_none() && engine::is_engine_function(name) {
+                (TokenType::Function, DEFAULT_LIBRARY)
+            } else {
+                (TokenType::Function, 0)
+            }
+        },
+        RefKind::Member | RefKind::PropertyKey => (TokenType::Property, 0),
+        RefKind::Include => (TokenType::Class, 0),
+    })
+}
+
+fn decl_token(kind: DeclKind) -> (TokenType, u32) {
+    match kind {
+        DeclKind::Function => (TokenType::Function, 0),
+        DeclKind::Param => (TokenType::Parameter, 0),
+        DeclKind::Var => (TokenType::Variable, 0),
+        DeclKind::Local => (TokenType::Property, 0),
+        DeclKind::Static => (TokenType::Variable, STATIC),
+        DeclKind::StaticConst => (TokenType::Variable, STATIC | READONLY),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// Decodes the tokens into their texts, types and modifiers.
+    fn decode(code: &str, data: &[u32]) -> Vec<(String, &'static str, Vec<&'static str>)> {
+        let lines: Vec<&str> = code.split('\n').collect();
+        let (mut line, mut start) = (0, 0);
+        data.chunks(5)
+            .map(|token| {
+                if token[0] > 0 {
+                    start = 0;
+                }
+                line += token[0] as usize;
+                start += token[1] as usize;
+                let text: String = lines[line].chars().skip(start).take(token[2] as usize).collect();
+                let modifiers = TOKEN_MODIFIERS.iter().enumerate()
+                    .filter(|(i, _)| token[4] & (1 << i) != 0)
+                    .map(|(_, m)| *m)
+                    .collect();
+                (text, TOKEN_TYPES[token[3] as usize], modifiers)
+            })
+            .collect()
+    }
+
+    fn tokens(code: &str, span: Option<Span>) -> Vec<(String, &'static str, Vec<&'static str>)> {
+        let script = parser::parse(code);
+        let names = Names {
+            declarations: scope::script_declarations(&script),
+            is_definition: &|name| name == "Rock" || name == "Library_Foo",
+        };
+        decode(code, &semantic_tokens(&script, code, &names, span))
+    }
+
+    fn token(text: &str, ty: &'static str, modifiers: &[&'static str]) -> (String, &'static str, Vec<&'static str>) {
+        (text.to_string(), ty, modifiers.to_vec())
+    }
+
+    const CODE: &str = "#include Library_Foo\n\
+        local count;\n\
+        static const MAX = 3;\n\
+        func Hit(int speed)\n\
+        {\n\
+        \tvar rock = Rock;\n\
+        \tcount = speed + MAX + unknown;\n\
+        \tFoo(\"ä $Hello$\", rock.Name);\n\
+        }\n";
+
+    #[test]
+    fn classifies_identifiers() {
+        assert_eq!(tokens(CODE, None), [
+            token("Library_Foo", "class", &[]),
+            token("count", "property", &["declaration"]),
+            token("MAX", "variable", &["declaration", "static", "readonly"]),
+            token("Hit", "function", &["declaration"]),
+            token("speed", "parameter", &["declaration"]),
+            token("rock", "variable", &["declaration"]),
+            token("Rock", "class", &[]),
+            token("count", "property", &[]),
+            token("speed", "parameter", &[]),
+            token("MAX", "variable", &["static", "readonly"]),
+            token("Foo", "function", &[]),
+            token("$Hello$", "macro", &[]),
+            token("rock", "variable", &[]),
+            token("Name", "property", &[]),
+        ]);
+    }
+
+    #[test]
+    fn only_returns_tokens_in_range() {
+        let start = CODE.find("\tvar").unwrap();
+        let end = CODE.find("\tcount").unwrap();
+        assert_eq!(tokens(CODE, Some(Span::new(start, end))), [
+            token("rock", "variable", &["declaration"]),
+            token("Rock", "class", &[]),
+        ]);
+    }
+}
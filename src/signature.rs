@@ -0,0 +1,135 @@
+//! Signature help: finding the call around the cursor and describing the
+//! parameters of the called function.
+
+use crate::ast;
+use crate::engine;
+use crate::lexer::{self, TokenKind};
+use lsp_types::{Documentation, MarkupContent, MarkupKind, ParameterInformation, ParameterLabel, SignatureInformation};
+
+/// Keywords that are followed by parentheses without being calls.
+const KEYWORDS: &[&str] = &["if", "while", "for", "return", "func"];
+
+/// Tokens that start an expression when followed by `{`.
+const PROPLIST_PREFIXES: &[&str] = &["(", "[", ",", "=", ":"];
+
+/// The call whose argument list contains the cursor.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CallContext {
+    pub name: String,
+    /// Index of the argument the cursor is in.
+    pub active_parameter: usize,
+}
+
+/// Finds the innermost call around the offset by scanning the tokens before
+/// it, so that it also works while the argument list is still incomplete.
+pub fn call_context(code: &str, offset: usize) -> Option<CallContext> {
+    let tokens: Vec<lexer::Token> = lexer::tokenize(code).into_iter()
+        .filter(|t| !t.is_comment() && t.span.end <= offset)
+        .collect();
+    let text = |i: usize| tokens[i].span.text(code);
+    let mut depth = 0;
+    let mut commas = 0;
+    for i in (0..tokens.len()).rev() {
+        if tokens[i].kind != TokenKind::Punct {
+            continue;
+        }
+        match text(i) {
+            ")" | "]" | "}" => depth += 1,
+            "(" | "[" | "{" if depth > 0 => depth -= 1,
+            "," if depth == 0 => commas += 1,
+            // A proplist literal in the arguments, as opposed to a block.
+            "{" if i > 0 && PROPLIST_PREFIXES.contains(&text(i - 1)) => commas = 0,
+            // The cursor can't be inside a call's argument list anymore.
+            ";" | "{" => return None,
+            "(" if i > 0 && tokens[i - 1].kind == TokenKind::Ident && !KEYWORDS.contains(&text(i - 1)) => {
+                // `func Name(` declares a function instead.
+                if i > 1 && text(i - 2) == "func" {
+                    return None;
+                }
+                return Some(CallContext { name: text(i - 1).to_string(), active_parameter: commas });
+            },
+            // Parenthesized expressions and array literals have commas of their own.
+            "(" | "[" => commas = 0,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Signature of an engine function with its documentation.
+pub fn engine_signature(f: &engine::Function) -> SignatureInformation {
+    let prefix = format!("{} {}", f.return_type, f.name);
+    let params = f.params.iter()
+        .map(|p| (format!("{} {}", p.ty, p.name).trim().to_string(), Some(p.description.to_string())))
+        .collect();
+    build(prefix.trim(), params, Some(f.description.to_string()))
+}
+
+/// Signature of a function declared in a script, including parameter types.
+pub fn script_signature(f: &ast::Function) -> SignatureInformation {
+    let prefix = format!("func {}", f.name.name);
+    let params = f.params.iter()
+        .map(|p| match &p.ty {
+            Some(ty) => (format!("{} {}", ty.name, p.name.name), None),
+            None => (p.name.name.clone(), None),
+        })
+        .collect();
+    build(&prefix, params, None)
+}
+
+fn build(prefix: &str, params: Vec<(String, Option<String>)>, doc: Option<String>) -> SignatureInformation {
+    let markdown = |value: String| Documentation::MarkupContent(MarkupContent { kind: MarkupKind::Markdown, value });
+    let mut label = format!("{}(", prefix);
+    let mut parameters = Vec::new();
+    for (i, (param, doc)) in params.into_iter().enumerate() {
+        if i > 0 {
+            label.push_str(", ");
+        }
+        let start = label.encode_utf16().count() as u64;
+        label.push_str(&param);
+        let end = label.encode_utf16().count() as u64;
+        parameters.push(ParameterInformation {
+            label: ParameterLabel::LabelOffsets([start, end]),
+            documentation: doc.filter(|d| !d.is_empty()).map(markdown),
+        });
+    }
+    label.push(')');
+    SignatureInformation {
+        label,
+        documentation: doc.filter(|d| !d.is_empty()).map(markdown),
+        parameters: Some(parameters),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(code: &str) -> Option<(String, usize)> {
+        let offset = code.find('|').unwrap();
+        call_context(&code.replace('|', ""), offset).map(|c| (c.name, c.active_parameter))
+    }
+
+    #[test]
+    fn finds_the_active_parameter() {
+        assert_eq!(context("Foo(|"), Some(("Foo".to_string(), 0)));
+        assert_eq!(context("Foo(1, Bar(2, 3), |"), Some(("Foo".to_string(), 2)));
+        assert_eq!(context("Foo(1, Bar(2, |"), Some(("Bar".to_string(), 1)));
+        assert_eq!(context("Foo(1, [2, 3], (4), |"), Some(("Foo".to_string(), 3)));
+    }
+
+    #[test]
+    fn skips_proplist_arguments() {
+        assert_eq!(context("Foo(1, {a = 1, b = 2}, |"), Some(("Foo".to_string(), 2)));
+        assert_eq!(context("Foo(1, {a = 1, b = |"), Some(("Foo".to_string(), 1)));
+        assert_eq!(context("Foo(1, {a = {b = |"), Some(("Foo".to_string(), 1)));
+    }
+
+    #[test]
+    fn ignores_declarations_and_blocks() {
+        assert_eq!(context("func Foo(a, |"), None);
+        assert_eq!(context("if (a) { |"), None);
+        assert_eq!(context("func Foo() { Bar(); |"), None);
+        assert_eq!(context("while (|"), None);
+    }
+}
@@ -0,0 +1,212 @@
+//! Outline of a script for `textDocument/documentSymbol`.
+
+use crate::ast::*;
+use crate::lexer::{self, Span};
+use crate::utils::LineIndex;
+use lsp_types::{DocumentSymbol, SymbolKind};
+use regex::Regex;
+
+/// Builds the outline of a script. Top-level declarations are grouped below
+/// the `/*-- Section --*/` comment preceding them.
+pub fn document_symbols(script: &Script, code: &str) -> Vec<DocumentSymbol> {
+    let index = LineIndex::new(code);
+    let section_re = Regex::new(lexer::SECTION_HEADER).unwrap();
+    let nodes = script.nodes();
+    // Only comments between declarations start a section.
+    let sections: Vec<(Span, String)> = script.comments.iter()
+        .filter(|c| !nodes.iter().any(|n| n.span().start < c.start && c.end <= n.span().end))
+        .filter_map(|c| section_re.captures(c.text(code)).map(|m| (*c, m[1].to_string())))
+        .filter(|(_, title)| !title.is_empty())
+        .collect();
+
+    let mut result = Vec::new();
+    let mut current: Option<(Span, String, Vec<DocumentSymbol>)> = None;
+    let mut sections = sections.into_iter().peekable();
+    for node in nodes {
+        while let Some((span, _)) = sections.peek() {
+            if span.start > node.span().start {
+                break;
+            }
+            let (span, title) = sections.next().unwrap();
+            result.extend(current.take().map(|s| section(&index, s)));
+            current = Some((span, title, Vec::new()));
+        }
+        let symbols = node_symbols(&index, node);
+        match &mut current {
+            Some((span, _, children)) => {
+                if !symbols.is_empty() {
+                    *span = span.to(node.span());
+                }
+                children.extend(symbols);
+            },
+            None => result.extend(symbols),
+        }
+    }
+    result.extend(current.take().map(|s| section(&index, s)));
+    result.extend(sections.map(|s| section(&index, (s.0, s.1, Vec::new()))));
+    result
+}
+
+fn section(index: &LineIndex, (span, title, children): (Span, String, Vec<DocumentSymbol>)) -> DocumentSymbol {
+    symbol(index, title, None, SymbolKind::Namespace, span, span, children)
+}
+
+fn node_symbols(index: &LineIndex, node: Node) -> Vec<DocumentSymbol> {
+    match node {
+        Node::Directive(d) => match (&d.kind, &d.argument) {
+            (DirectiveKind::Include, Some(id)) | (DirectiveKind::Appendto, Some(id)) => {
+                let detail = if d.kind == DirectiveKind::Include { "#include" } else { "#appendto" };
+                vec![symbol(index, id.name.clone(), Some(detail.to_string()), SymbolKind::Module, d.span, id.span, Vec::new())]
+            },
+            _ => Vec::new(),
+        },
+        Node::Function(f) => function_symbol(index, f).into_iter().collect(),
+        Node::VarDecl(v) => v.vars.iter()
+            .filter(|var| !var.name.name.is_empty())
+            .map(|var| {
+                let (kind, detail) = match v.scope {
+                    VarScope::Var => (SymbolKind::Variable, "var"),
+                    VarScope::Local => (SymbolKind::Field, "local"),
+                    VarScope::Static => (SymbolKind::Variable, "static"),
+                    VarScope::StaticConst => (SymbolKind::Constant, "static const"),
+                };
+                let span = var.value.as_ref().map_or(var.name.span, |value| var.name.span.to(value.span));
+                let children = var.value.as_ref().map_or_else(Vec::new, |value| value_symbols(index, value));
+                symbol(index, var.name.name.clone(), Some(detail.to_string()), kind, span, var.name.span, children)
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn function_symbol(index: &LineIndex, f: &Function) -> Option<DocumentSymbol> {
+    if f.name.name.is_empty() {
+        return None;
+    }
+    let kind = if f.visibility == Some(Visibility::Global) { SymbolKind::Function } else { SymbolKind::Method };
+    Some(symbol(index, f.name.name.clone(), Some(function_detail(f)), kind, f.span, f.name.span, Vec::new()))
+}
+
+/// Visibility and parameters, e.g. `public func(int x, y)`.
+fn function_detail(f: &Function) -> String {
+    let visibility = match f.visibility {
+        Some(Visibility::Public) => "public ",
+        Some(Visibility::Protected) => "protected ",
+        Some(Visibility::Private) => "private ",
+        Some(Visibility::Global) => "global ",
+        None => "",
+    };
+    let params: Vec<String> = f.params.iter()
+        .map(|p| match &p.ty {
+            Some(ty) => format!("{} {}", ty.name, p.name.name),
+            None => p.name.name.clone(),
+        })
+        .collect();
+    format!("{}func({})", visibility, params.join(", "))
+}
+
+/// Entries of proplist values such as an `ActMap`, recursively.
+fn value_symbols(index: &LineIndex, value: &Expr) -> Vec<DocumentSymbol> {
+    let props = match &value.kind {
+        ExprKind::Proplist { props, .. } => props,
+        _ => return Vec::new(),
+    };
+    props.iter()
+        .filter(|p| !p.key.name.is_empty())
+        .map(|p| match &p.value.kind {
+            ExprKind::Function(f) => {
+                symbol(index, p.key.name.clone(), Some(function_detail(f)), SymbolKind::Method, p.span, p.key.span, Vec::new())
+            },
+            ExprKind::Proplist { prototype, .. } => {
+                let detail = prototype.as_ref().map(|proto| format!("new {}", proto.name));
+                symbol(index, p.key.name.clone(), detail, SymbolKind::Object, p.span, p.key.span, value_symbols(index, &p.value))
+            },
+            _ => symbol(index, p.key.name.clone(), None, SymbolKind::Property, p.span, p.key.span, Vec::new()),
+        })
+        .collect()
+}
+
+fn symbol(index: &LineIndex, name: String, detail: Option<String>, kind: SymbolKind, span: Span, name_span: Span, children: Vec<DocumentSymbol>) -> DocumentSymbol {
+    DocumentSymbol {
+        name,
+        detail,
+        kind,
+        deprecated: None,
+        range: index.range(span),
+        selection_range: index.range(name_span),
+        children: if children.is_empty() { None } else { Some(children) },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// Names, kinds, details, ranges and selection starts of the symbols,
+    /// with children indented.
+    fn outline(code: &str) -> Vec<String> {
+        fn add(symbols: &[DocumentSymbol], depth: usize, result: &mut Vec<String>) {
+            for s in symbols {
+                result.push(format!("{}{} {:?} [{}] {}:{}-{}:{} {}:{}",
+                    "  ".repeat(depth), s.name, s.kind, s.detail.as_deref().unwrap_or(""),
+                    s.range.start.line, s.range.start.character, s.range.end.line, s.range.end.character,
+                    s.selection_range.start.line, s.selection_range.start.character));
+                add(s.children.as_deref().unwrap_or(&[]), depth + 1, result);
+            }
+        }
+        let mut result = Vec::new();
+        add(&document_symbols(&parser::parse(code), code), 0, &mut result);
+        result
+    }
+
+    #[test]
+    fn groups_declarations_into_sections() {
+        let code = "#include Rock\n\
+            \n\
+            local Size = 5;\n\
+            static Count;\n\
+            static const Max = 10;\n\
+            \n\
+            /*-- Interaction --*/\n\
+            \n\
+            public func Hit(int speed, obj)\n\
+            {\n\
+            \treturn;\n\
+            }\n\
+            \n\
+            local ActMap = {\n\
+            \tWalk = {\n\
+            \t\tSpeed = 10,\n\
+            \t},\n\
+            };\n\
+            \n\
+            /*-- Globals --*/\n\
+            \n\
+            global func Explode() {}\n";
+        assert_eq!(outline(code), vec![
+            "Rock Module [#include] 0:0-0:13 0:9",
+            "Size Field [local] 2:6-2:14 2:6",
+            "Count Variable [static] 3:7-3:12 3:7",
+            "Max Constant [static const] 4:13-4:21 4:13",
+            "Interaction Namespace [] 6:0-17:1 6:0",
+            "  Hit Method [public func(int speed, obj)] 8:0-11:1 8:12",
+            "  ActMap Field [local] 13:6-17:1 13:6",
+            "    Walk Object [] 14:1-16:2 14:1",
+            "      Speed Property [] 15:2-15:12 15:2",
+            "Globals Namespace [] 19:0-21:24 19:0",
+            "  Explode Function [global func()] 21:0-21:24 21:12",
+        ]);
+    }
+
+    #[test]
+    fn keeps_sections_without_declarations() {
+        let code = "func Main() {}\n\
+            \n\
+            /*-- Unused --*/\n";
+        assert_eq!(outline(code), vec![
+            "Main Method [func()] 0:0-0:14 0:5",
+            "Unused Namespace [] 2:0-2:16 2:0",
+        ]);
+    }
+}
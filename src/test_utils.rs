@@ -0,0 +1,34 @@
+//! Helpers shared by the tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A directory of files for a test, removed again when dropped.
+pub struct TempTree {
+    path: PathBuf,
+}
+
+impl TempTree {
+    /// Creates the files, given by their paths relative to the tree. `name`
+    /// keeps the trees of tests running in parallel apart.
+    pub fn new(name: &str, files: &[(&str, &str)]) -> TempTree {
+        let path = std::env::temp_dir().join(format!("oclsp-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        for (file, content) in files {
+            let file = path.join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, content).unwrap();
+        }
+        TempTree { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempTree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,135 @@
+//! Conversion between byte offsets and LSP positions.
+//!
+//! LSP counts columns in UTF-16 code units while the parser works on byte
+//! offsets into the UTF-8 source.
+
+use crate::ast::Script;
+use crate::lexer::Span;
+use crate::scope;
+use lsp_types::{Position, Range, SelectionRange};
+
+/// Precomputed line starts for repeated position conversions.
+pub struct LineIndex<'a> {
+    code: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(code: &'a str) -> LineIndex<'a> {
+        // Lines end at LF, CRLF or a lone CR.
+        let bytes = code.as_bytes();
+        let mut line_starts = vec![0];
+        line_starts.extend(bytes.iter().enumerate()
+            .filter(|&(i, &b)| b == b'\n' || b == b'\r' && bytes.get(i + 1) != Some(&b'\n'))
+            .map(|(i, _)| i + 1));
+        LineIndex { code, line_starts }
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.code.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let start = self.line_starts[line];
+        let character = self.code.get(start..offset).map_or(0, |s| s.encode_utf16().count());
+        Position { line: line as u64, character: character as u64 }
+    }
+
+    /// Returns the byte offset of the position, or None if it is outside the
+    /// document. Columns past the end of a line are clamped.
+    pub fn offset(&self, pos: Position) -> Option<usize> {
+        let start = *self.line_starts.get(pos.line as usize)?;
+        let end = self.line_starts.get(pos.line as usize + 1).map_or(self.code.len(), |e| e - 1);
+        let line = self.code[start..end].trim_end_matches('\r');
+        let end = start + line.len();
+        let mut units = 0;
+        for (i, c) in line.char_indices() {
+            if units >= pos.character {
+                return Some(start + i);
+            }
+            units += c.len_utf16() as u64;
+        }
+        Some(end)
+    }
+
+    pub fn range(&self, span: Span) -> Range {
+        Range { start: self.position(span.start), end: self.position(span.end) }
+    }
+}
+
+/// Converts a span to an LSP range.
+pub fn range(code: &str, span: Span) -> Range {
+    LineIndex::new(code).range(span)
+}
+
+/// Builds nested selection ranges around a position, from the identifier
+/// outwards to the whole file.
+pub fn selection_ranges(script: &Script, code: &str, pos: Position) -> Option<SelectionRange> {
+    let index = LineIndex::new(code);
+    let offset = index.offset(pos)?;
+    let mut spans = vec![Span::new(0, code.len())];
+    spans.extend(script.nodes_at(offset).iter().map(|node| node.span()));
+    if let Some(occurrence) = scope::occurrence_at(script, offset) {
+        spans.push(occurrence.ident.span);
+    }
+    spans.dedup();
+    spans.into_iter().fold(None, |parent, span| Some(SelectionRange {
+        range: index.range(span),
+        parent: parent.map(Box::new),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn converts_utf16_positions() {
+        let code = "ä😀b\r\nx\u{c}y\n";
+        let index = LineIndex::new(code);
+        assert_eq!(index.position(code.find('b').unwrap()), Position { line: 0, character: 3 });
+        assert_eq!(index.offset(Position { line: 0, character: 3 }), code.find('b'));
+        // Past the end of the line, before the CRLF.
+        assert_eq!(index.offset(Position { line: 0, character: 10 }), code.find('\r'));
+        assert_eq!(index.offset(Position { line: 1, character: 2 }), code.find('y'));
+        assert_eq!(index.offset(Position { line: 3, character: 0 }), None);
+    }
+
+    #[test]
+    fn breaks_lines_at_lone_cr() {
+        let code = "a\rbc\r\nd";
+        let index = LineIndex::new(code);
+        assert_eq!(index.position(code.find('c').unwrap()), Position { line: 1, character: 1 });
+        assert_eq!(index.position(code.find('d').unwrap()), Position { line: 2, character: 0 });
+        assert_eq!(index.offset(Position { line: 0, character: 5 }), Some(1));
+        assert_eq!(index.offset(Position { line: 1, character: 5 }), code.find("\r\n"));
+    }
+
+    #[test]
+    fn selection_ranges_grow_outwards() {
+        let code = "func f(a)\n{\n\tif (a)\n\t{\n\t\tLog(\"%d\", a + 1);\n\t}\n}\n";
+        let script = parser::parse(code);
+        let pos = Position { line: 4, character: 13 };
+        let mut texts = Vec::new();
+        let mut range = selection_ranges(&script, code, pos);
+        let index = LineIndex::new(code);
+        while let Some(r) = range {
+            let span = Span::new(index.offset(r.range.start).unwrap(), index.offset(r.range.end).unwrap());
+            texts.push(span.text(code).to_string());
+            range = r.parent.map(|p| *p);
+        }
+        assert_eq!(texts, [
+            "a",
+            "a + 1",
+            "Log(\"%d\", a + 1)",
+            "Log(\"%d\", a + 1);",
+            "{\n\t\tLog(\"%d\", a + 1);\n\t}",
+            "if (a)\n\t{\n\t\tLog(\"%d\", a + 1);\n\t}",
+            "{\n\tif (a)\n\t{\n\t\tLog(\"%d\", a + 1);\n\t}\n}",
+            "func f(a)\n{\n\tif (a)\n\t{\n\t\tLog(\"%d\", a + 1);\n\t}\n}",
+            code,
+        ]);
+    }
+}
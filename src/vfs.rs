@@ -0,0 +1,175 @@
+//! File access that also looks into packed groups.
+//!
+//! A file inside a packed group is addressed by appending its path within
+//! the group to the path of the group file, just like it would be if the
+//! group were unpacked. Such files are exposed to the client with read-only
+//! `c4group:` URIs.
+
+use crate::c4group::{Entry, Group};
+use crate::workspace::is_group;
+use lsp_types::Url;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// URI scheme for files inside packed groups.
+pub const GROUP_SCHEME: &str = "c4group";
+
+/// Opened groups with their modification time.
+type GroupCache = BTreeMap<PathBuf, (Option<SystemTime>, Arc<Group>)>;
+
+/// Shared by all threads, so that each group is only read once.
+static GROUPS: Mutex<GroupCache> = Mutex::new(BTreeMap::new());
+
+/// Packed group files found while listing directories, so that telling
+/// whether a path is inside of one doesn't check each of its ancestors on
+/// disk.
+static PACKED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Takes note of whether a group is packed, as it may have been packed or
+/// unpacked since it was last listed.
+fn set_packed(path: &Path, packed: bool) {
+    let mut groups = PACKED.lock().unwrap_or_else(|e| e.into_inner());
+    if packed {
+        groups.insert(path.to_path_buf());
+    } else {
+        groups.remove(path);
+    }
+}
+
+/// Splits a path into the outermost packed group file it is in and the path
+/// inside of that group. Only groups that have been listed are known.
+fn split(path: &Path) -> Option<(&Path, &Path)> {
+    let packed = PACKED.lock().unwrap_or_else(|e| e.into_inner());
+    let group = path.ancestors().skip(1)
+        .filter(|p| packed.contains(*p))
+        .last()?;
+    Some((group, path.strip_prefix(group).ok()?))
+}
+
+fn open_group(path: &Path) -> io::Result<Arc<Group>> {
+    let modified = fs::metadata(path)?.modified().ok();
+    // Holding the lock while reading keeps other threads from reading the
+    // same group again.
+    let mut groups = GROUPS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((time, group)) = groups.get(path) {
+        if *time == modified {
+            return Ok(group.clone());
+        }
+    }
+    let group = Arc::new(Group::open(path)?);
+    groups.insert(path.to_path_buf(), (modified, group.clone()));
+    Ok(group)
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such entry in group")
+}
+
+/// Whether the path points into a packed group.
+pub fn is_packed(path: &Path) -> bool {
+    split(path).is_some()
+}
+
+/// Reads a file from disk or from a packed group.
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let (group_path, inner) = match split(path) {
+        Some(split) => split,
+        None => return fs::read(path),
+    };
+    match open_group(group_path)?.get(inner) {
+        Some(Entry::File(Some(contents))) => Ok(contents.clone()),
+        _ => Err(not_found()),
+    }
+}
+
+pub fn is_file(path: &Path) -> bool {
+    match split(path) {
+        Some((group_path, inner)) => open_group(group_path)
+            .map_or(false, |group| matches!(group.get(inner), Some(Entry::File(_)))),
+        None => path.is_file(),
+    }
+}
+
+/// Lists a directory, returning the path of each entry and whether it is a
+/// directory. Packed groups are listed like directories.
+pub fn read_dir(path: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
+    let list = |group: &Group| group.entries.iter()
+        .map(|(name, entry)| (path.join(name), matches!(entry, Entry::Group(_))))
+        .collect();
+    if is_group(path) && path.is_file() {
+        set_packed(path, true);
+        return Ok(list(&*open_group(path)?));
+    }
+    let (group_path, inner) = match split(path) {
+        Some(split) => split,
+        None => {
+            return Ok(fs::read_dir(path)?
+                .filter_map(Result::ok)
+                .map(|entry| {
+                    let path = entry.path();
+                    let dir = path.is_dir();
+                    let group = is_group(&path);
+                    if group {
+                        set_packed(&path, !dir);
+                    }
+                    (path, dir || group)
+                })
+                .collect());
+        },
+    };
+    match open_group(group_path)?.get(inner) {
+        Some(Entry::Group(group)) => Ok(list(group)),
+        _ => Err(not_found()),
+    }
+}
+
+/// Converts a path to a `file:` URI, or a `c4group:` URI if it points into a
+/// packed group.
+pub fn to_uri(path: &Path) -> Option<Url> {
+    let uri = Url::from_file_path(path).ok()?;
+    if is_packed(path) {
+        Url::parse(&uri.as_str().replacen("file:", &format!("{}:", GROUP_SCHEME), 1)).ok()
+    } else {
+        Some(uri)
+    }
+}
+
+/// Converts a `file:` or `c4group:` URI to a path.
+pub fn to_path(uri: &Url) -> Option<PathBuf> {
+    match uri.scheme() {
+        "file" => uri.to_file_path().ok(),
+        GROUP_SCHEME => Url::parse(&uri.as_str().replacen(GROUP_SCHEME, "file", 1)).ok()?.to_file_path().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempTree;
+
+    #[test]
+    fn knows_listed_packed_groups() {
+        let tree = TempTree::new("vfs-packed", &[
+            ("Packed.ocd", ""),
+            ("Unpacked.ocd/Script.c", ""),
+        ]);
+        let packed = tree.path().join("Packed.ocd/Rock.ocd/Script.c");
+        assert!(!is_packed(&packed));
+        let mut entries = read_dir(tree.path()).unwrap();
+        entries.sort();
+        assert_eq!(entries, vec![(tree.path().join("Packed.ocd"), true), (tree.path().join("Unpacked.ocd"), true)]);
+        assert!(is_packed(&packed));
+        assert_eq!(split(&packed), Some((&*tree.path().join("Packed.ocd"), Path::new("Rock.ocd/Script.c"))));
+        assert!(!is_packed(&tree.path().join("Unpacked.ocd/Script.c")));
+
+        fs::remove_file(tree.path().join("Packed.ocd")).unwrap();
+        fs::create_dir(tree.path().join("Packed.ocd")).unwrap();
+        read_dir(tree.path()).unwrap();
+        assert!(!is_packed(&packed));
+    }
+}
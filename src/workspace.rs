@@ -0,0 +1,279 @@
+//! Knowledge about scripts on disk: workspace roots and the definitions in them.
+
+use crate::vfs;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// File extensions of OpenClonk groups (definitions, scenarios, folders, system groups).
+const GROUP_EXTENSIONS: &[&str] = &["ocd", "ocs", "ocf", "ocg"];
+/// Groups of the OpenClonk planet directory that most scripts depend on.
+const PLANET_GROUPS: &[&str] = &["System.ocg", "Objects.ocd", "Decoration.ocd"];
+
+pub struct Workspace {
+    roots: Vec<PathBuf>,
+    /// The `planet` directory of an OpenClonk installation.
+    planet: Option<PathBuf>,
+    /// Maps definition IDs to their Script.c. Built lazily on first use.
+    definitions: Option<Arc<HashMap<String, PathBuf>>>,
+    /// All scripts below the roots and the planet groups. Built lazily on first use.
+    scripts: Option<Arc<Vec<PathBuf>>>,
+}
+
+impl Workspace {
+    pub fn new(roots: Vec<PathBuf>) -> Workspace {
+        Workspace { roots, planet: None, definitions: None, scripts: None }
+    }
+
+    /// Makes sure definitions around a file outside of all workspace roots are
+    /// known by adding its outermost enclosing group as another root. Files
+    /// outside of any group are standalone scripts, so that opening one
+    /// doesn't scan whatever directory it happens to be in. Returns whether
+    /// a root was added.
+    pub fn add_file(&mut self, path: &Path) -> bool {
+        if self.search_paths().iter().any(|root| path.starts_with(root)) {
+            return false;
+        }
+        let root = path.ancestors().skip(1)
+            .filter(|dir| is_group(dir))
+            .last();
+        if let Some(root) = root {
+            self.roots.push(root.to_path_buf());
+            self.refresh();
+            return true;
+        }
+        false
+    }
+
+    /// Takes note of a saved file. Only new scripts and changed DefCore.txt
+    /// files require scanning the workspace again. Returns whether it will
+    /// be scanned again.
+    pub fn file_saved(&mut self, path: &Path) -> bool {
+        let known = self.scripts.as_ref()
+            .map_or(false, |scripts| scripts.iter().any(|script| script == path));
+        if !known || path.file_name().map_or(false, |name| name == "DefCore.txt") {
+            self.refresh();
+            return true;
+        }
+        false
+    }
+
+    /// Sets the OpenClonk planet directory whose stock content is used in
+    /// addition to the workspace roots. Returns whether it changed.
+    pub fn set_planet(&mut self, planet: Option<PathBuf>) -> bool {
+        if self.planet == planet {
+            return false;
+        }
+        self.planet = planet;
+        self.refresh();
+        true
+    }
+
+    /// Whether the path belongs to the stock content of the planet directory
+    /// rather than to the workspace. Such scripts are only used to look up
+    /// names, never to find or change references.
+    pub fn is_planet(&self, path: &Path) -> bool {
+        match &self.planet {
+            Some(planet) => path.starts_with(planet) && !self.roots.iter().any(|root| path.starts_with(root)),
+            None => false,
+        }
+    }
+
+    /// The workspace roots followed by the stock groups of the planet
+    /// directory, so that workspace definitions take precedence.
+    fn search_paths(&self) -> Vec<PathBuf> {
+        let planet_groups = self.planet.iter()
+            .flat_map(|planet| PLANET_GROUPS.iter().map(move |group| planet.join(group)))
+            .filter(|group| group.exists());
+        self.roots.iter().cloned().chain(planet_groups).collect()
+    }
+
+    /// Forgets known definitions and scripts so that they are scanned again
+    /// on next use.
+    pub fn refresh(&mut self) {
+        self.definitions = None;
+        self.scripts = None;
+    }
+
+    /// Lists all script files below the workspace roots.
+    pub fn scripts(&mut self) -> Arc<Vec<PathBuf>> {
+        if self.scripts.is_none() {
+            let mut scripts = Vec::new();
+            for root in self.search_paths() {
+                scan_scripts(&root, &mut scripts);
+            }
+            scripts.sort();
+            scripts.dedup();
+            self.scripts = Some(Arc::new(scripts));
+        }
+        self.scripts.clone().unwrap()
+    }
+
+    /// Maps the IDs of all definitions to their scripts.
+    pub fn definitions(&mut self) -> Arc<HashMap<String, PathBuf>> {
+        if self.definitions.is_none() {
+            let mut definitions = HashMap::new();
+            for root in self.search_paths() {
+                scan_definitions(&root, &mut definitions);
+            }
+            self.definitions = Some(Arc::new(definitions));
+        }
+        self.definitions.clone().unwrap()
+    }
+
+    /// Returns the script of the definition with the given ID.
+    pub fn definition_script(&mut self, id: &str) -> Option<PathBuf> {
+        self.definitions().get(id).cloned()
+    }
+}
+
+/// Whether the path names an OpenClonk group by its extension.
+pub fn is_group(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str())
+        .map_or(false, |ext| GROUP_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Whether the path names a definition folder.
+pub fn is_definition(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("ocd"))
+}
+
+/// Collects definitions below `dir`, including sub-definitions nested in
+/// other definitions.
+fn scan_definitions(dir: &Path, definitions: &mut HashMap<String, PathBuf>) {
+    if is_definition(dir) {
+        if let Some(id) = read_defcore_id(&dir.join("DefCore.txt")) {
+            let script = dir.join("Script.c");
+            if vfs::is_file(&script) {
+                definitions.entry(id).or_insert(script);
+            }
+        }
+    }
+    let entries = match vfs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for (path, is_dir) in entries {
+        if is_dir && !is_hidden(&path) {
+            scan_definitions(&path, definitions);
+        }
+    }
+}
+
+fn scan_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) {
+    let entries = match vfs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for (path, is_dir) in entries {
+        if is_hidden(&path) {
+            continue;
+        }
+        if is_dir {
+            scan_scripts(&path, scripts);
+        } else if path.extension().map_or(false, |ext| ext == "c") {
+            scripts.push(path);
+        }
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().map_or(false, |name| name.to_string_lossy().starts_with('.'))
+}
+
+/// Reads the `id=` entry of a DefCore.txt.
+pub fn read_defcore_id(path: &Path) -> Option<String> {
+    let content = vfs::read(path).ok()?;
+    defcore_id(&String::from_utf8_lossy(&content)).map(|(_, id)| id)
+}
+
+/// Finds the `id=` entry in the `[DefCore]` section, returning its line
+/// number and the ID.
+pub fn defcore_id(content: &str) -> Option<(usize, String)> {
+    let mut section = "";
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            section = &line[1..line.len() - 1];
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if key.trim() == "id" && (section.is_empty() || section == "DefCore") => {
+                return Some((i, value.trim().to_string()));
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempTree;
+
+    #[test]
+    fn adds_groups_of_loose_files_as_roots() {
+        let tree = TempTree::new("workspace-add-file", &[
+            ("Project/Main.c", ""),
+            ("Loose.ocd/Rock.ocd/DefCore.txt", "[DefCore]\nid=Rock\n"),
+            ("Loose.ocd/Rock.ocd/Script.c", ""),
+        ]);
+        let mut workspace = Workspace::new(vec![tree.path().join("Project")]);
+        assert!(!workspace.add_file(&tree.path().join("Project/Main.c")));
+        assert!(workspace.definitions().is_empty());
+        let rock = tree.path().join("Loose.ocd/Rock.ocd/Script.c");
+        assert!(workspace.add_file(&rock));
+        assert_eq!(workspace.definition_script("Rock"), Some(rock.clone()));
+        assert!(!workspace.add_file(&rock));
+    }
+
+    #[test]
+    fn scans_again_after_saving_a_defcore() {
+        let tree = TempTree::new("workspace-file-saved", &[
+            ("Rock.ocd/DefCore.txt", "[DefCore]\nid=Rock\n"),
+            ("Rock.ocd/Script.c", ""),
+        ]);
+        let mut workspace = Workspace::new(vec![tree.path().to_path_buf()]);
+        let script = tree.path().join("Rock.ocd/Script.c");
+        assert_eq!(workspace.definition_script("Rock"), Some(script.clone()));
+        assert_eq!(*workspace.scripts(), vec![script.clone()]);
+        assert!(!workspace.file_saved(&script));
+        let defcore = tree.path().join("Rock.ocd/DefCore.txt");
+        std::fs::write(&defcore, "[DefCore]\nid=Stone\n").unwrap();
+        assert!(workspace.file_saved(&defcore));
+        assert_eq!(workspace.definition_script("Stone"), Some(script));
+        assert_eq!(workspace.definition_script("Rock"), None);
+    }
+
+    #[test]
+    fn reads_defcore_ids() {
+        assert_eq!(defcore_id("[DefCore]\nid=Rock\n"), Some((1, "Rock".to_string())));
+        assert_eq!(defcore_id("[DefCore]\nName=Rock\n  id =  Rock  \r\n"), Some((2, "Rock".to_string())));
+        assert_eq!(defcore_id("id=Rock\n"), Some((0, "Rock".to_string())));
+        assert_eq!(defcore_id("[DefCore]\nName=Rock\n"), None);
+        assert_eq!(defcore_id("[Physical]\nid=Rock\n"), None);
+    }
+
+    #[test]
+    fn finds_nested_definitions() {
+        let tree = TempTree::new("workspace-nested", &[
+            ("Vehicles.ocd/DefCore.txt", "[DefCore]\nid=Vehicles\n"),
+            ("Vehicles.ocd/Script.c", ""),
+            ("Vehicles.ocd/Lorry.ocd/DefCore.txt", "[DefCore]\nid = Lorry\n"),
+            ("Vehicles.ocd/Lorry.ocd/Script.c", ""),
+            ("Vehicles.ocd/Lorry.ocd/Wheel.ocd/DefCore.txt", "[DefCore]\nid=Wheel\n"),
+            ("Vehicles.ocd/Lorry.ocd/Wheel.ocd/Script.c", ""),
+            ("Vehicles.ocd/Broken.ocd/DefCore.txt", "[DefCore]\nName=Broken\n"),
+            ("Vehicles.ocd/Broken.ocd/Script.c", ""),
+            ("Vehicles.ocd/.Hidden.ocd/DefCore.txt", "[DefCore]\nid=Hidden\n"),
+            ("Vehicles.ocd/.Hidden.ocd/Script.c", ""),
+        ]);
+        let mut workspace = Workspace::new(vec![tree.path().to_path_buf()]);
+        let mut ids: Vec<String> = workspace.definitions().keys().cloned().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["Lorry", "Vehicles", "Wheel"]);
+        assert_eq!(workspace.definition_script("Wheel"), Some(tree.path().join("Vehicles.ocd/Lorry.ocd/Wheel.ocd/Script.c")));
+    }
+}